@@ -0,0 +1,116 @@
+use super::*;
+use clap::{Parser, Subcommand};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(version, about = "orthodontics for the mind")]
+pub(crate) struct Cli {
+	/// Use this database instead of the default one.
+	#[arg(long, global = true)]
+	pub(crate) db: Option<PathBuf>,
+
+	/// Without a command, the GUI is launched.
+	#[command(subcommand)]
+	pub(crate) command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub(crate) enum Command {
+	/// Stream a completion to stdout. The prompt is read from stdin if not given.
+	Ask {
+		/// Defaults to the `openai_model` setting.
+		#[arg(long)]
+		model: Option<String>,
+		#[arg(long)]
+		system: Option<String>,
+		prompt: Vec<String>,
+	},
+	/// Print the most recently saved document.
+	Export {
+		/// Print every saved document as JSON lines instead.
+		#[arg(long)]
+		all: bool,
+	},
+	/// Search past prompts and saved documents.
+	Search {
+		query: String,
+		#[arg(long, default_value_t = 20)]
+		limit: i64,
+	},
+}
+
+pub(crate) fn parse() -> Cli {
+	Cli::parse()
+}
+
+pub(crate) async fn run(command: Command) -> Result<(), Box<dyn std::error::Error>> {
+	match command {
+		Command::Ask { model, system, prompt } => ask(model, system, prompt).await,
+		Command::Export { all } => export(all),
+		Command::Search { query, limit } => search(&query, limit),
+	}
+}
+
+async fn ask(
+	model: Option<String>,
+	system: Option<String>,
+	prompt: Vec<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+	let prompt = if prompt.is_empty() {
+		let mut prompt = String::new();
+		std::io::stdin().read_to_string(&mut prompt)?;
+		prompt
+	} else {
+		prompt.join(" ")
+	};
+
+	let mut messages = Vec::new();
+	if let Some(system) = system {
+		messages.push(ChatMessage::new(System, system));
+	}
+	messages.push(ChatMessage::new(User, prompt.clone()));
+
+	Prompt { rowid: None, time_ms: now_ms(), prompt }.insert()?;
+
+	let mut params = GenerationParams::from_settings();
+	if let Some(model) = model {
+		params.model = model;
+	}
+
+	let (_trigger, tripwire) = Tripwire::new();
+	run_openai(params, tripwire, messages, |content| {
+		print!("{}", content);
+		std::io::stdout().flush().ok();
+	})
+	.await?;
+	println!();
+
+	Ok(())
+}
+
+fn export(all: bool) -> Result<(), Box<dyn std::error::Error>> {
+	if all {
+		for document in select!(Vec<Document> "ORDER BY timestamp_ms")? {
+			let line = serde_json::json!({
+				"title": document.title,
+				"timestamp_ms": document.timestamp_ms,
+				"content": document.content,
+			});
+			println!("{}", line);
+		}
+	} else if let Some(document) = select!(Option<Document> "ORDER BY timestamp_ms DESC LIMIT 1")? {
+		print!("{}", document.content);
+	}
+
+	Ok(())
+}
+
+/// Prints tab-separated `kind`, `time_ms` and the first line of each match, newest first.
+fn search(query: &str, limit: i64) -> Result<(), Box<dyn std::error::Error>> {
+	for result in search::search(query, limit)? {
+		println!("{}\t{}\t{}", result.kind(), result.time_ms(), search::first_line(result.content()));
+	}
+
+	Ok(())
+}
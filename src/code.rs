@@ -0,0 +1,113 @@
+use super::*;
+use egui_extras::syntax_highlighting::{self, CodeTheme};
+use std::ops::Range;
+
+/// A ```` ``` ```` fenced code block in a message.
+pub(crate) struct CodeBlock {
+	/// The info string after the opening fence, e.g. `rust`.
+	pub(crate) language: String,
+	/// Byte range of the code between the fences.
+	pub(crate) code: Range<usize>,
+}
+
+/// Finds the fenced code blocks in `text`. One that isn't closed yet, as while it's being typed or
+/// streamed, runs to the end.
+pub(crate) fn code_blocks(text: &str) -> Vec<CodeBlock> {
+	let mut blocks = Vec::new();
+	let mut open: Option<(String, usize)> = None;
+	let mut offset = 0;
+
+	for line in text.split_inclusive('\n') {
+		if let Some(info) = line.trim_start().strip_prefix("```") {
+			match open.take() {
+				None => open = Some((info.trim().to_owned(), offset + line.len())),
+				Some((language, start)) if info.trim().is_empty() => {
+					blocks.push(CodeBlock { language, code: start..offset })
+				}
+				// A fence with an info string can't close a block.
+				Some(block) => open = Some(block),
+			}
+		}
+		offset += line.len();
+	}
+	if let Some((language, start)) = open {
+		blocks.push(CodeBlock { language, code: start..text.len() });
+	}
+
+	blocks
+}
+
+/// The file extension for a code block's language, which is also how syntect finds most syntaxes.
+fn extension(language: &str) -> String {
+	let language = language.split_whitespace().next().unwrap_or_default().to_lowercase();
+	match language.as_str() {
+		"" | "text" | "plaintext" => "txt",
+		"rust" => "rs",
+		"python" | "python3" => "py",
+		"javascript" | "node" => "js",
+		"bash" | "shell" | "zsh" | "console" => "sh",
+		"c++" => "cpp",
+		"c#" | "csharp" => "cs",
+		"ruby" => "rb",
+		"golang" => "go",
+		"haskell" => "hs",
+		"perl" => "pl",
+		"markdown" => "md",
+		"yml" => "yaml",
+		other => other,
+	}
+	.to_owned()
+}
+
+/// Appends `text` to `job` in `format`, with the code in its code blocks colored by language.
+pub(crate) fn layout(ui: &Ui, job: &mut LayoutJob, text: &str, format: TextFormat) {
+	let theme = CodeTheme::from_style(ui.style());
+	let mut end = 0;
+
+	for block in code_blocks(text) {
+		job.append(&text[end..block.code.start], 0.0, format.clone());
+
+		let code = &text[block.code.clone()];
+		let highlighted =
+			syntax_highlighting::highlight(ui.ctx(), &theme, code, &extension(&block.language));
+		// The highlighter picks its own font; only its colors are kept.
+		for section in &highlighted.sections {
+			job.append(
+				&highlighted.text[section.byte_range.clone()],
+				0.0,
+				TextFormat { color: section.format.color, italics: section.format.italics, ..format.clone() },
+			);
+		}
+
+		end = block.code.end;
+	}
+
+	job.append(&text[end..], 0.0, format);
+}
+
+/// A row of copy and save buttons for each code block in `text`.
+pub(crate) fn buttons(ui: &mut Ui, text: &str) {
+	for (i, block) in code_blocks(text).into_iter().enumerate() {
+		let code = &text[block.code];
+		ui.horizontal(|ui| {
+			let language = if block.language.is_empty() { "code" } else { &block.language };
+			ui.label(RichText::new(format!("block {} ({})", i + 1, language)).weak());
+			if ui.small_button("📋 copy").clicked() {
+				ui.output_mut(|o| o.copied_text = code.to_owned());
+			}
+			if ui.small_button("💾 save to file…").clicked() {
+				save(code, &extension(&block.language));
+			}
+		});
+	}
+}
+
+fn save(code: &str, extension: &str) {
+	let Some(path) = rfd::FileDialog::new().set_file_name(format!("code.{}", extension)).save_file()
+	else {
+		return;
+	};
+	if let Err(e) = std::fs::write(&path, code) {
+		eprintln!("{}: {}", path.display(), e);
+	}
+}
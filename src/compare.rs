@@ -0,0 +1,273 @@
+use super::*;
+
+/// One side-by-side run, recorded when a preferred response is picked.
+#[derive(Turbosql, Default)]
+pub(crate) struct Comparison {
+	pub(crate) rowid: Option<i64>,
+	pub(crate) time_ms: i64,
+	/// JSON array of `[role, content]` pairs sent to every model.
+	pub(crate) messages: String,
+	/// JSON array of `[model, response]` pairs, one per column.
+	pub(crate) responses: String,
+	pub(crate) preferred_model: String,
+}
+
+pub(crate) struct CompareColumn {
+	/// Identifies the column within its window, whatever columns are added or removed.
+	id: u64,
+	model: String,
+	content: String,
+	/// The run whose response this column shows; updates from earlier runs are ignored.
+	run: u64,
+	running: bool,
+	trigger: Option<Trigger>,
+}
+
+pub(crate) struct CompareWindow {
+	id: WindowId,
+	open: bool,
+	messages: Vec<ChatMessage>,
+	/// The context, until it's been read and put before `messages`.
+	context: Option<context::Snapshot>,
+	params: GenerationParams,
+	columns: Vec<CompareColumn>,
+	next_column: u64,
+	/// Incremented on every send.
+	run: u64,
+	preferred: Option<usize>,
+	/// Rowid of the `Comparison` recorded for the current responses, if any.
+	comparison: Option<i64>,
+}
+
+impl CompareWindow {
+	fn add_column(&mut self, model: &str) {
+		self.columns.push(CompareColumn {
+			id: self.next_column,
+			model: model.to_owned(),
+			content: String::new(),
+			run: 0,
+			running: false,
+			trigger: None,
+		});
+		self.next_column += 1;
+	}
+
+	fn running(&self) -> bool {
+		self.columns.iter().any(|c| c.running)
+	}
+}
+
+/// Progress of one column's response, sent by its completion task.
+pub(crate) struct Update {
+	window: WindowId,
+	column: u64,
+	run: u64,
+	kind: UpdateKind,
+}
+
+enum UpdateKind {
+	Delta(String),
+	/// The response ended, with its error if it failed.
+	Finished(Option<String>),
+}
+
+/// Applies `update` if its column still exists and hasn't been sent again since.
+pub(crate) fn apply(windows: &mut [CompareWindow], update: Update) {
+	let Some(column) = windows
+		.iter_mut()
+		.find(|w| w.id == update.window)
+		.and_then(|w| w.columns.iter_mut().find(|c| c.id == update.column))
+		.filter(|c| c.run == update.run)
+	else {
+		return;
+	};
+	match update.kind {
+		UpdateKind::Delta(content) => column.content.push_str(&content),
+		UpdateKind::Finished(error) => {
+			column.running = false;
+			column.trigger = None;
+			if let Some(e) = error {
+				column.content.push_str(&format!("\n\n[error: {}]", e));
+			}
+		}
+	}
+}
+
+/// Opens a comparison window for `messages` after `context`, with one column per model in the
+/// `compare_models` setting.
+pub(crate) fn open(
+	store: &mut state::Store,
+	messages: Vec<ChatMessage>,
+	context: context::Snapshot,
+	params: GenerationParams,
+) {
+	let mut window = CompareWindow {
+		id: WindowId::new(),
+		open: true,
+		messages,
+		context: Some(context),
+		params,
+		columns: Vec::new(),
+		next_column: 0,
+		run: 0,
+		preferred: None,
+		comparison: None,
+	};
+	for model in settings::get("compare_models").lines().filter(|m| !m.is_empty()) {
+		window.add_column(model);
+	}
+	store.compare_windows.push(window);
+}
+
+/// Draws the comparison windows, and removes closed ones, cancelling their responses.
+pub(crate) fn show(ctx: &Context, store: &mut state::Store) {
+	let events = store.sender();
+	for window in store.compare_windows.iter_mut() {
+		if let Some(context) = &window.context
+			&& let std::task::Poll::Ready(message) = context.poll()
+		{
+			window.messages.splice(0..0, message);
+			window.context = None;
+		}
+		let id = window.id;
+		let mut open = window.open;
+		egui::Window::new(format!("compare {}", id)).id(Id::new(("compare", id))).open(&mut open).show(
+			ctx,
+			|ui| {
+				CollapsingHeader::new(format!("{} messages", window.messages.len()))
+					.id_source(("compare_messages", id))
+					.show(ui, |ui| {
+						for message in &window.messages {
+							ui.label(format!("[{}]: {}", message.role, message.content));
+						}
+					});
+
+				ui.horizontal(|ui| {
+					let running = window.running() || window.context.is_some();
+					if ui.add_enabled(!running && !window.columns.is_empty(), Button::new("send to all")).clicked()
+					{
+						send(window, &events, ctx);
+						window.preferred = None;
+						window.comparison = None;
+					}
+					if window.columns.len() < 4 && ui.button("add model").clicked() {
+						let model = window.params.model.clone();
+						window.add_column(&model);
+					}
+					if window.columns.len() > 2 && ui.button("remove model").clicked() {
+						window.columns.pop();
+						window.preferred = window.preferred.filter(|&p| p < window.columns.len());
+					}
+					if let Some(preferred) = window.preferred
+						&& ui.button("add preferred to dataset").clicked()
+					{
+						let mut messages = window.messages.clone();
+						messages.push(ChatMessage::new(Assistant, window.columns[preferred].content.clone()));
+						if let Err(e) = dataset::add(&messages) {
+							eprintln!("{}", e);
+						}
+					}
+					if ui.button("save models as default").clicked() {
+						let models = window.columns.iter().map(|c| c.model.as_str()).collect::<Vec<_>>();
+						if let Err(e) = settings::set("compare_models", &models.join("\n")) {
+							eprintln!("{}", e);
+						}
+					}
+				});
+
+				ui.separator();
+
+				if window.columns.is_empty() {
+					ui.label("no models; add one to compare");
+					return;
+				}
+
+				let mut preferred = None;
+				ui.columns(window.columns.len(), |uis| {
+					for (column_num, (ui, column)) in uis.iter_mut().zip(window.columns.iter_mut()).enumerate() {
+						ui.add_enabled(
+							!column.running,
+							TextEdit::singleline(&mut column.model).desired_width(f32::INFINITY),
+						);
+						let finished = !column.running && !column.content.is_empty();
+						if ui
+							.add_enabled(
+								finished,
+								SelectableLabel::new(window.preferred == Some(column_num), "👍 prefer"),
+							)
+							.clicked()
+						{
+							preferred = Some(column_num);
+						}
+						if column.running {
+							ui.spinner();
+						}
+						ScrollArea::vertical().id_source(("compare_column", id, column.id)).show(ui, |ui| {
+							ui.label(&column.content);
+						});
+					}
+				});
+
+				if let Some(preferred) = preferred {
+					window.preferred = Some(preferred);
+					record(&window.messages, &window.columns, preferred, &mut window.comparison);
+				}
+			},
+		);
+		window.open = open;
+	}
+	store.compare_windows.retain(|w| w.open);
+}
+
+fn send(window: &mut CompareWindow, events: &state::Sender, ctx: &Context) {
+	window.run += 1;
+	for column in window.columns.iter_mut() {
+		column.content.clear();
+		column.run = window.run;
+		column.running = true;
+		let (trigger, tripwire) = Tripwire::new();
+		column.trigger = Some(trigger);
+
+		let params = GenerationParams { model: column.model.clone(), ..window.params.clone() };
+		let messages = window.messages.clone();
+		let (window_id, column_id, run) = (window.id, column.id, window.run);
+		let update =
+			move |kind| Event::Compare(Update { window: window_id, column: column_id, run, kind });
+		let events = events.clone();
+		let ctx = ctx.clone();
+		tokio::spawn(async move {
+			let deltas = events.clone();
+			let repaint = ctx.clone();
+			let result = run_openai(params, tripwire, messages, move |content| {
+				deltas.send(update(UpdateKind::Delta(content.clone()))).ok();
+				repaint.request_repaint();
+			})
+			.await;
+			let error = result.err().map(|e| e.to_string());
+			events.send(update(UpdateKind::Finished(error))).ok();
+			ctx.request_repaint();
+		});
+	}
+}
+
+fn record(
+	messages: &[ChatMessage],
+	columns: &[CompareColumn],
+	preferred: usize,
+	rowid: &mut Option<i64>,
+) {
+	let messages = messages.iter().map(|m| (m.role.to_string(), &m.content)).collect::<Vec<_>>();
+	let responses = columns.iter().map(|c| (&c.model, &c.content)).collect::<Vec<_>>();
+	let comparison = Comparison {
+		rowid: *rowid,
+		time_ms: now_ms(),
+		messages: serde_json::to_string(&messages).unwrap(),
+		responses: serde_json::to_string(&responses).unwrap(),
+		preferred_model: columns[preferred].model.clone(),
+	};
+	if comparison.rowid.is_some() {
+		comparison.update().unwrap();
+	} else {
+		*rowid = Some(comparison.insert().unwrap());
+	}
+}
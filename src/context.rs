@@ -0,0 +1,263 @@
+use super::*;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::task::Poll;
+use tokio::sync::watch;
+
+/// Files larger than this are left out of context blocks.
+const MAX_FILE_BYTES: u64 = 1_000_000;
+
+/// A directory read stops at whichever of these it reaches first.
+const MAX_TOTAL_BYTES: u64 = 4_000_000;
+const MAX_FILES: usize = 1_000;
+
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) enum Source {
+	/// A text file or directory, read in the background when attached and again on every send.
+	Path(PathBuf),
+	/// Readable text of a fetched web page.
+	Web { url: String, text: String },
+}
+
+/// Content sent along with a wheel window's messages.
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct ContextAttachment {
+	pub(crate) source: Source,
+	pub(crate) token_count: usize,
+	/// The last read, finished or in progress; read again after the workspace is restored.
+	#[serde(skip)]
+	content: Option<Read>,
+}
+
+/// A read's content, or `None` while it's in progress.
+type Read = watch::Receiver<Option<Arc<Content>>>;
+
+/// The read's content once it's finished, or empty if the reading thread died.
+fn finished(read: &Read) -> Option<Arc<Content>> {
+	let content = read.borrow().clone();
+	match content {
+		None if read.has_changed().is_err() => {
+			Some(Arc::new(Content::new(String::new(), Some("read failed".to_owned()))))
+		}
+		content => content,
+	}
+}
+
+/// An attachment's context blocks, as of its last read.
+struct Content {
+	blocks: String,
+	token_count: usize,
+	/// Why the read stopped early, if it did.
+	truncated: Option<String>,
+}
+
+impl Content {
+	fn new(blocks: String, truncated: Option<String>) -> Self {
+		Self { token_count: token_count(&blocks), blocks, truncated }
+	}
+}
+
+impl ContextAttachment {
+	pub(crate) fn new(path: PathBuf) -> Self {
+		Self::from_source(Source::Path(path))
+	}
+
+	pub(crate) fn from_web(url: &str, text: &str) -> Self {
+		Self::from_source(Source::Web { url: url.to_owned(), text: text.to_owned() })
+	}
+
+	fn from_source(source: Source) -> Self {
+		let mut attachment = Self { source, token_count: 0, content: None };
+		attachment.read();
+		attachment
+	}
+
+	/// Starts reading the attachment again, off the UI thread.
+	pub(crate) fn read(&mut self) {
+		let (tx, rx) = watch::channel(None);
+		match &self.source {
+			Source::Path(path) => {
+				let path = path.clone();
+				std::thread::spawn(move || {
+					tx.send(Some(Arc::new(read_path(&path)))).ok();
+				});
+			}
+			Source::Web { url, text } => {
+				let blocks = format!("<url href=\"{}\">\n{}\n</url>\n", url, text.trim_end());
+				tx.send(Some(Arc::new(Content::new(blocks, None)))).ok();
+			}
+		}
+		self.content = Some(rx);
+	}
+
+	/// The current read, started if there isn't one; updates `token_count` once it's finished.
+	fn content(&mut self) -> Read {
+		if self.content.is_none() {
+			self.read();
+		}
+		let read = self.content.clone().unwrap();
+		if let Some(content) = finished(&read) {
+			self.token_count = content.token_count;
+		}
+		read
+	}
+
+	pub(crate) fn label(&self) -> String {
+		match &self.source {
+			Source::Path(path) => path.display().to_string(),
+			Source::Web { url, .. } => url.clone(),
+		}
+	}
+
+	pub(crate) fn pick_files() -> Vec<Self> {
+		rfd::FileDialog::new().pick_files().unwrap_or_default().into_iter().map(Self::new).collect()
+	}
+
+	pub(crate) fn pick_folder() -> Option<Self> {
+		rfd::FileDialog::new().pick_folder().map(Self::new)
+	}
+}
+
+/// Reads a file, or every file under a directory that isn't excluded by `.gitignore`, up to
+/// [`MAX_FILES`] files and [`MAX_TOTAL_BYTES`].
+fn read_path(path: &Path) -> Content {
+	let mut blocks = String::new();
+	let root = path.parent().unwrap_or(path);
+	let (mut files, mut bytes) = (0, 0);
+
+	for entry in ignore::WalkBuilder::new(path).sort_by_file_name(|a, b| a.cmp(b)).build() {
+		let entry = match entry {
+			Ok(entry) => entry,
+			Err(e) => {
+				eprintln!("{}", e);
+				continue;
+			}
+		};
+		let Some(len) = entry
+			.file_type()
+			.is_some_and(|t| t.is_file())
+			.then(|| entry.metadata().map(|m| m.len()).ok())
+			.flatten()
+			.filter(|&len| len <= MAX_FILE_BYTES)
+		else {
+			continue;
+		};
+		if files == MAX_FILES || bytes + len > MAX_TOTAL_BYTES {
+			let limit = format!("stopped at {} files, {} KB", files, bytes / 1000);
+			eprintln!("{}: {}", path.display(), limit);
+			return Content::new(blocks, Some(limit));
+		}
+		// Non-UTF-8 files are assumed to be binary and skipped.
+		if let Ok(content) = std::fs::read_to_string(entry.path()) {
+			blocks.push_str(&block(entry.path().strip_prefix(root).unwrap_or(entry.path()), &content));
+			files += 1;
+			bytes += len;
+		}
+	}
+
+	Content::new(blocks, None)
+}
+
+fn block(path: &Path, content: &str) -> String {
+	format!("<file path=\"{}\">\n{}\n</file>\n", path.display(), content.trim_end())
+}
+
+/// The attachments' total tokens as of their last finished reads.
+pub(crate) fn tokens(attachments: &mut [ContextAttachment]) -> usize {
+	let mut tokens = 0;
+	for attachment in attachments.iter_mut() {
+		attachment.content();
+		tokens += attachment.token_count;
+	}
+	tokens
+}
+
+/// Attachments' reads, finished or not, to combine into a system message once they're done.
+#[derive(Clone)]
+pub(crate) struct Snapshot(Vec<Read>);
+
+/// Reads `attachments` again, so edits since the last read are picked up.
+pub(crate) fn reread(attachments: &mut [ContextAttachment]) {
+	for attachment in attachments {
+		attachment.read();
+	}
+}
+
+/// The current reads of `attachments`, starting any that haven't been read yet.
+pub(crate) fn snapshot(attachments: &mut [ContextAttachment]) -> Snapshot {
+	Snapshot(attachments.iter_mut().map(ContextAttachment::content).collect())
+}
+
+impl Snapshot {
+	/// The combined system message, if there are any attachments, once every read has finished.
+	pub(crate) fn poll(&self) -> Poll<Option<ChatMessage>> {
+		let Some(contents) = self.0.iter().map(finished).collect::<Option<Vec<_>>>() else {
+			return Poll::Pending;
+		};
+		if contents.is_empty() {
+			return Poll::Ready(None);
+		}
+		Poll::Ready(Some(ChatMessage {
+			id: MessageId::new(),
+			role: System,
+			content: contents.iter().map(|c| c.blocks.as_str()).collect(),
+			token_count: contents.iter().map(|c| c.token_count).sum(),
+			images: Vec::new(),
+			pinned: false,
+		}))
+	}
+
+	/// Waits for the reads to finish, then combines them.
+	pub(crate) async fn message(mut self) -> Option<ChatMessage> {
+		for read in &mut self.0 {
+			// Fails only if the reading thread died, which `poll` reports as a failed read.
+			read.wait_for(Option::is_some).await.ok();
+		}
+		match self.poll() {
+			Poll::Ready(message) => message,
+			Poll::Pending => unreachable!("every read has finished"),
+		}
+	}
+}
+
+/// Lists attachments with their token counts, with buttons to add, remove and re-read them.
+pub(crate) fn ui(ui: &mut Ui, attachments: &mut Vec<ContextAttachment>) {
+	let mut remove = None;
+
+	for (i, attachment) in attachments.iter_mut().enumerate() {
+		let content = finished(&attachment.content());
+		ui.horizontal(|ui| {
+			if ui.small_button("✖").on_hover_text("remove from context").clicked() {
+				remove = Some(i);
+			}
+			match content {
+				None => {
+					ui.label(format!("{} (reading…)", attachment.label()));
+					ui.spinner();
+				}
+				Some(content) => {
+					ui.label(format!("{} ({} tokens)", attachment.label(), content.token_count));
+					if let Some(truncated) = &content.truncated {
+						ui.label(RichText::new(truncated).color(ui.visuals().warn_fg_color));
+					}
+				}
+			}
+		});
+	}
+
+	ui.horizontal(|ui| {
+		if ui.button("📄 attach files").clicked() {
+			attachments.extend(ContextAttachment::pick_files());
+		}
+		if ui.button("📁 attach folder").clicked() {
+			attachments.extend(ContextAttachment::pick_folder());
+		}
+		if ui.button("⟳ re-read").clicked() {
+			reread(attachments);
+		}
+	});
+
+	if let Some(i) = remove {
+		attachments.remove(i);
+	}
+}
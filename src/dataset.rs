@@ -0,0 +1,412 @@
+use super::*;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicBool, Ordering};
+use transcript::Format;
+
+/// Rough per-message overhead of the chat format, as counted by OpenAI.
+const TOKENS_PER_MESSAGE: usize = 3;
+
+/// Set when examples are added outside the dataset window, so it reloads them.
+static CHANGED: AtomicBool = AtomicBool::new(false);
+
+/// A conversation saved for fine-tuning.
+#[derive(Turbosql, Default)]
+pub(crate) struct DatasetExample {
+	pub(crate) rowid: Option<i64>,
+	pub(crate) time_ms: i64,
+	/// One line of fine-tuning JSONL.
+	pub(crate) messages: String,
+	/// `good`, `bad`, or empty if not yet reviewed. Only good examples are exported.
+	pub(crate) tag: String,
+	/// SHA-256 of `messages`, to find duplicates. `None` for examples saved by older versions.
+	pub(crate) hash: Option<String>,
+}
+
+fn hash(messages: &str) -> String {
+	format!("{:x}", Sha256::digest(messages))
+}
+
+/// Saves `messages` as an untagged example, unless the dataset already has them.
+pub(crate) fn add(messages: &[ChatMessage]) -> Result<(), Box<dyn std::error::Error>> {
+	let messages = transcript::export(Format::Jsonl, messages).trim_end().to_owned();
+	let hash = hash(&messages);
+	if select!(Option<DatasetExample> "WHERE hash = " hash " OR messages = " messages)?.is_some() {
+		return Err("already in the dataset".into());
+	}
+	DatasetExample { rowid: None, time_ms: now_ms(), messages, tag: String::new(), hash: Some(hash) }
+		.insert()?;
+	CHANGED.store(true, Ordering::Relaxed);
+	Ok(())
+}
+
+pub(crate) fn example_tokens(messages: &[ChatMessage]) -> usize {
+	TOKENS_PER_MESSAGE
+		+ messages.iter().map(|m| TOKENS_PER_MESSAGE + token_count(&m.content)).sum::<usize>()
+}
+
+/// Problems that would get `messages` rejected as a chat fine-tuning example.
+pub(crate) fn validate(messages: &[ChatMessage], tokens: usize, max_tokens: usize) -> Vec<String> {
+	let mut problems = Vec::new();
+
+	// An optional system message, then user and assistant turns alternating.
+	let skip = usize::from(messages.first().is_some_and(|m| m.role == System));
+	for (i, message) in messages.iter().enumerate().skip(skip) {
+		let expected = if (i - skip) % 2 == 0 { User } else { Assistant };
+		if message.role != expected {
+			problems.push(format!("message {} is {}, expected {}", i + 1, message.role, expected));
+			break;
+		}
+	}
+	if messages.len() == skip {
+		problems.push("no user or assistant messages".into());
+	} else if messages.last().is_some_and(|m| m.role != Assistant) {
+		problems.push("must end with an assistant message".into());
+	}
+	if messages.iter().any(|m| m.content.trim().is_empty() && m.images.is_empty()) {
+		problems.push("has an empty message".into());
+	}
+	if tokens > max_tokens {
+		problems.push(format!("{} tokens, over the limit of {}", tokens, max_tokens));
+	}
+
+	problems
+}
+
+struct Entry {
+	row: DatasetExample,
+	messages: Vec<ChatMessage>,
+	tokens: usize,
+	problems: Vec<String>,
+}
+
+impl Entry {
+	fn new(row: DatasetExample, max_tokens: usize) -> Self {
+		let mut entry = Self { row, messages: Vec::new(), tokens: 0, problems: Vec::new() };
+		match transcript::import(Format::Jsonl, &entry.row.messages) {
+			Ok(mut conversations) if conversations.len() == 1 => {
+				entry.messages = conversations.remove(0);
+				entry.check(max_tokens);
+			}
+			Ok(_) => entry.problems.push("expected exactly one conversation".into()),
+			Err(e) => entry.problems.push(e.to_string()),
+		}
+		entry
+	}
+
+	fn check(&mut self, max_tokens: usize) {
+		self.tokens = example_tokens(&self.messages);
+		self.problems = validate(&self.messages, self.tokens, max_tokens);
+	}
+
+	fn is_exported(&self) -> bool {
+		self.row.tag == "good" && self.problems.is_empty()
+	}
+
+	fn title(&self) -> String {
+		let first_user = self.messages.iter().find(|m| m.role == User).map_or("", |m| m.content.as_str());
+		let first_line = first_user.lines().next().unwrap_or_default();
+		let tag = match self.row.tag.as_str() {
+			"good" => "👍",
+			"bad" => "👎",
+			_ => "·",
+		};
+		format!("{} {} ({} tokens)", tag, first_line, self.tokens)
+	}
+
+	fn save(&mut self) {
+		self.row.messages = transcript::export(Format::Jsonl, &self.messages).trim_end().to_owned();
+		self.row.hash = Some(hash(&self.row.messages));
+		if let Err(e) = self.row.update() {
+			eprintln!("{}", e);
+		}
+	}
+}
+
+#[derive(Default)]
+struct Stats {
+	good: usize,
+	bad: usize,
+	untagged: usize,
+	/// Good examples that pass validation, and so are exported.
+	exported: usize,
+	messages: usize,
+	tokens: usize,
+	min_tokens: usize,
+	max_tokens: usize,
+}
+
+impl Stats {
+	fn new(entries: &[Entry]) -> Self {
+		let mut stats = Self { min_tokens: usize::MAX, ..Default::default() };
+		for entry in entries {
+			match entry.row.tag.as_str() {
+				"good" => stats.good += 1,
+				"bad" => stats.bad += 1,
+				_ => stats.untagged += 1,
+			}
+			if entry.is_exported() {
+				stats.exported += 1;
+				stats.messages += entry.messages.len();
+				stats.tokens += entry.tokens;
+				stats.min_tokens = stats.min_tokens.min(entry.tokens);
+				stats.max_tokens = stats.max_tokens.max(entry.tokens);
+			}
+		}
+		if stats.exported == 0 {
+			stats.min_tokens = 0;
+		}
+		stats
+	}
+
+	fn ui(&self, ui: &mut Ui) {
+		Grid::new("dataset_stats").num_columns(2).show(ui, |ui| {
+			let rows = [
+				("good / bad / untagged", format!("{} / {} / {}", self.good, self.bad, self.untagged)),
+				("exported examples", self.exported.to_string()),
+				("messages", self.messages.to_string()),
+				("tokens", self.tokens.to_string()),
+				(
+					"tokens per example",
+					format!(
+						"min {}, mean {}, max {}",
+						self.min_tokens,
+						self.tokens.checked_div(self.exported).unwrap_or_default(),
+						self.max_tokens
+					),
+				),
+			];
+			for (label, value) in rows {
+				ui.label(label);
+				ui.label(value);
+				ui.end_row();
+			}
+		});
+	}
+}
+
+#[derive(Default)]
+pub(crate) struct DatasetWindow {
+	pub(crate) open: bool,
+	/// Loaded when the window is first shown.
+	entries: Option<Vec<Entry>>,
+	/// Which tag to list; `None` lists every example.
+	filter: Option<&'static str>,
+	message: Option<String>,
+	/// The last deleted example, until it's restored or another is deleted.
+	deleted: Option<Entry>,
+}
+
+impl DatasetWindow {
+	pub(crate) fn show(&mut self, ctx: &Context) {
+		if !self.open {
+			return;
+		}
+
+		let mut open = self.open;
+		egui::Window::new("dataset").open(&mut open).show(ctx, |ui| self.ui(ui));
+		self.open = open;
+	}
+
+	pub(crate) fn ui(&mut self, ui: &mut Ui) {
+		let max_tokens = settings::get("finetune_max_tokens").parse().unwrap();
+		if CHANGED.swap(false, Ordering::Relaxed) {
+			self.entries = None;
+		}
+		let entries =
+			self.entries.get_or_insert_with(|| match select!(Vec<DatasetExample> "ORDER BY time_ms DESC") {
+				Ok(rows) => rows.into_iter().map(|row| Entry::new(row, max_tokens)).collect(),
+				Err(e) => {
+					eprintln!("{}", e);
+					Vec::new()
+				}
+			});
+
+		Stats::new(entries).ui(ui);
+
+		ui.horizontal(|ui| {
+			if ui.button("export good examples…").clicked() {
+				self.message = export(entries);
+			}
+			if ui.button("⟳ re-check").clicked() {
+				for entry in entries.iter_mut() {
+					entry.check(max_tokens);
+				}
+			}
+		});
+		if let Some(message) = &self.message {
+			ui.label(message);
+		}
+		if let Some(deleted) = &self.deleted {
+			ui.horizontal(|ui| {
+				ui.label(format!("deleted {}", deleted.title()));
+				if ui.button("↶ undo").clicked() {
+					let mut row = self.deleted.take().unwrap().row;
+					row.rowid = None;
+					match row.insert() {
+						Ok(_) => self.entries = None,
+						Err(e) => eprintln!("{}", e),
+					}
+				}
+			});
+		}
+
+		ui.separator();
+
+		ui.horizontal(|ui| {
+			ui.label("show:");
+			ui.selectable_value(&mut self.filter, None, "all");
+			ui.selectable_value(&mut self.filter, Some("good"), "good");
+			ui.selectable_value(&mut self.filter, Some("bad"), "bad");
+			ui.selectable_value(&mut self.filter, Some(""), "untagged");
+		});
+
+		let mut delete = None;
+
+		ScrollArea::vertical().show(ui, |ui| {
+			for (i, entry) in entries.iter_mut().enumerate() {
+				if self.filter.is_some_and(|tag| entry.row.tag != tag) {
+					continue;
+				}
+				CollapsingHeader::new(entry.title()).id_source(("dataset", entry.row.rowid)).show(ui, |ui| {
+					if entry_ui(ui, entry, max_tokens) {
+						delete = Some(i);
+					}
+				});
+			}
+		});
+
+		if let Some(i) = delete {
+			let entry = entries.remove(i);
+			match execute!("DELETE FROM datasetexample WHERE rowid = ?", entry.row.rowid) {
+				Ok(_) => self.deleted = Some(entry),
+				Err(e) => eprintln!("{}", e),
+			}
+		}
+	}
+}
+
+/// Shows one example with its assistant turns editable; returns whether delete was clicked.
+fn entry_ui(ui: &mut Ui, entry: &mut Entry, max_tokens: usize) -> bool {
+	let mut delete = false;
+
+	ui.horizontal(|ui| {
+		let mut tag = entry.row.tag.clone();
+		ui.selectable_value(&mut tag, "good".to_owned(), "👍 good");
+		ui.selectable_value(&mut tag, "bad".to_owned(), "👎 bad");
+		ui.selectable_value(&mut tag, String::new(), "untagged");
+		if tag != entry.row.tag {
+			entry.row.tag = tag;
+			entry.save();
+		}
+		delete = ui.button("🗑 delete").clicked();
+	});
+
+	for problem in &entry.problems {
+		ui.colored_label(ui.visuals().error_fg_color, problem);
+	}
+
+	let mut edited = false;
+	for message in entry.messages.iter_mut() {
+		if message.role == Assistant {
+			let response = ui.add(TextEdit::multiline(&mut message.content).desired_width(f32::INFINITY));
+			edited |= response.lost_focus();
+		} else {
+			ui.label(format!("[{}]: {}", message.role, message.content));
+		}
+	}
+	if edited {
+		entry.check(max_tokens);
+		entry.save();
+	}
+
+	delete
+}
+
+/// Asks where to save, then writes every good example that passes validation.
+fn export(entries: &[Entry]) -> Option<String> {
+	let path = rfd::FileDialog::new()
+		.add_filter("JSONL", &["jsonl"])
+		.set_file_name("dataset.jsonl")
+		.save_file()?;
+	let exported = entries.iter().filter(|e| e.is_exported()).collect::<Vec<_>>();
+	let jsonl =
+		exported.iter().map(|e| transcript::export(Format::Jsonl, &e.messages)).collect::<String>();
+	let skipped = entries.iter().filter(|e| e.row.tag == "good" && !e.is_exported()).count();
+	Some(match std::fs::write(&path, jsonl) {
+		Ok(()) => format!(
+			"wrote {} examples to {}; skipped {} good examples with problems",
+			exported.len(),
+			path.display(),
+			skipped
+		),
+		Err(e) => format!("{}: {}", path.display(), e),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn conversation<const N: usize>(turns: [(Role, &str); N]) -> Vec<ChatMessage> {
+		turns.into_iter().map(|(role, content)| ChatMessage::new(role, content.to_owned())).collect()
+	}
+
+	fn problems(messages: &[ChatMessage]) -> Vec<String> {
+		validate(messages, example_tokens(messages), 1000)
+	}
+
+	#[test]
+	fn accepts_alternating_turns_after_a_system_message() {
+		assert!(problems(&conversation([(User, "hi"), (Assistant, "hello")])).is_empty());
+		let with_system = conversation([(System, "be brief"), (User, "hi"), (Assistant, "hello")]);
+		assert!(problems(&with_system).is_empty());
+	}
+
+	#[test]
+	fn rejects_out_of_order_roles() {
+		let messages = conversation([(User, "hi"), (User, "again"), (Assistant, "hello")]);
+		assert_eq!(problems(&messages), ["message 2 is user, expected assistant"]);
+		let messages = conversation([(Assistant, "hello"), (User, "hi"), (Assistant, "hello")]);
+		assert_eq!(problems(&messages), ["message 1 is assistant, expected user"]);
+		let messages = conversation([(User, "hi"), (System, "late"), (Assistant, "hello")]);
+		assert_eq!(problems(&messages), ["message 2 is system, expected assistant"]);
+	}
+
+	#[test]
+	fn rejects_no_turns() {
+		assert_eq!(problems(&[]), ["no user or assistant messages"]);
+		assert_eq!(problems(&conversation([(System, "be brief")])), ["no user or assistant messages"]);
+	}
+
+	#[test]
+	fn rejects_ending_without_a_reply() {
+		let messages = conversation([(User, "hi"), (Assistant, "hello"), (User, "bye")]);
+		assert_eq!(problems(&messages), ["must end with an assistant message"]);
+	}
+
+	#[test]
+	fn rejects_empty_messages_without_images() {
+		let messages = conversation([(User, " \n"), (Assistant, "hello")]);
+		assert_eq!(problems(&messages), ["has an empty message"]);
+
+		let mut messages = messages;
+		messages[0].images.push(ImageAttachment {
+			uri: "bytes://image.png".into(),
+			mime: "image/png",
+			bytes: Vec::new().into(),
+			hash: String::new(),
+		});
+		assert!(problems(&messages).is_empty());
+	}
+
+	#[test]
+	fn rejects_too_many_tokens() {
+		let messages = conversation([(User, "hi"), (Assistant, "hello")]);
+		let tokens = example_tokens(&messages);
+		assert!(validate(&messages, tokens, tokens).is_empty());
+		assert_eq!(
+			validate(&messages, tokens, tokens - 1),
+			[format!("{} tokens, over the limit of {}", tokens, tokens - 1)]
+		);
+	}
+}
@@ -0,0 +1,194 @@
+use async_openai::types::{
+	ChatCompletionRequestMessageContentPart, ChatCompletionRequestMessageContentPartImageArgs,
+	ChatCompletionRequestMessageContentPartTextArgs, ImageUrlArgs,
+};
+use base64::prelude::*;
+use egui::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use turbosql::{select, Blob, Turbosql};
+
+static NEXT_ATTACHMENT: AtomicUsize = AtomicUsize::new(0);
+
+/// How a saved workspace refers to an image in the database.
+const REFERENCE_PREFIX: &str = "sha256:";
+
+/// Image bytes referred to by hash from the saved workspace, which would otherwise carry them on
+/// every save.
+#[derive(Turbosql, Default)]
+struct StoredImage {
+	rowid: Option<i64>,
+	hash: String,
+	bytes: Blob,
+}
+
+/// An image attached to a user message, sent to the model as a `data:` URL.
+#[derive(Clone)]
+pub(crate) struct ImageAttachment {
+	/// `bytes://` URI under which egui's image loaders find this image.
+	pub(crate) uri: String,
+	pub(crate) mime: &'static str,
+	pub(crate) bytes: Arc<[u8]>,
+	/// SHA-256 of `bytes`, in hex.
+	pub(crate) hash: String,
+}
+
+impl ImageAttachment {
+	pub(crate) fn from_bytes(name: &str, bytes: Vec<u8>) -> Result<Self, Box<dyn std::error::Error>> {
+		let mime = match image::guess_format(&bytes)? {
+			image::ImageFormat::Png => "image/png",
+			image::ImageFormat::Jpeg => "image/jpeg",
+			format => Err(format!("{}: unsupported image format {:?}", name, format))?,
+		};
+		let n = NEXT_ATTACHMENT.fetch_add(1, Ordering::Relaxed);
+		let hash = format!("{:x}", Sha256::digest(&bytes));
+		Ok(Self { uri: format!("bytes://attachment/{}/{}", n, name), mime, bytes: bytes.into(), hash })
+	}
+
+	/// Parses a base64 `data:` URL, as made by `data_url`.
+	pub(crate) fn from_data_url(url: &str) -> Result<Self, Box<dyn std::error::Error>> {
+		let (_, data) = url.split_once(";base64,").ok_or("only base64 data: image URLs are supported")?;
+		Self::from_bytes("image", BASE64_STANDARD.decode(data)?)
+	}
+
+	pub(crate) fn from_path(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+		let name = path.file_name().unwrap_or_default().to_string_lossy();
+		Self::from_bytes(&name, std::fs::read(path)?)
+	}
+
+	pub(crate) fn from_dropped_file(file: &DroppedFile) -> Option<Self> {
+		let image = match (&file.path, &file.bytes) {
+			(Some(path), _) => Self::from_path(path),
+			(None, Some(bytes)) => Self::from_bytes(&file.name, bytes.to_vec()),
+			(None, None) => return None,
+		};
+		image.map_err(|e| eprintln!("{}", e)).ok()
+	}
+
+	/// Reads an image from the system clipboard, re-encoding it as PNG.
+	pub(crate) fn from_clipboard() -> Result<Self, Box<dyn std::error::Error>> {
+		let data = arboard::Clipboard::new()?.get_image()?;
+		let image =
+			image::RgbaImage::from_raw(data.width as u32, data.height as u32, data.bytes.into_owned())
+				.ok_or("clipboard image has unexpected size")?;
+		let mut png = std::io::Cursor::new(Vec::new());
+		image.write_to(&mut png, image::ImageFormat::Png)?;
+		Self::from_bytes("clipboard.png", png.into_inner())
+	}
+
+	/// Opens a file dialog and returns every image that could be read.
+	pub(crate) fn pick_files() -> Vec<Self> {
+		rfd::FileDialog::new()
+			.add_filter("image", &["png", "jpg", "jpeg"])
+			.pick_files()
+			.unwrap_or_default()
+			.iter()
+			.filter_map(|path| Self::from_path(path).map_err(|e| eprintln!("{}", e)).ok())
+			.collect()
+	}
+
+	pub(crate) fn data_url(&self) -> String {
+		format!("data:{};base64,{}", self.mime, BASE64_STANDARD.encode(&self.bytes))
+	}
+
+	/// Saves the bytes to the database, unless they're already there.
+	pub(crate) fn store(&self) -> Result<(), Box<dyn std::error::Error>> {
+		let hash = self.hash.as_str();
+		if select!(Option<StoredImage> "WHERE hash = " hash)?.is_none() {
+			StoredImage { rowid: None, hash: self.hash.clone(), bytes: self.bytes.to_vec() }.insert()?;
+		}
+		Ok(())
+	}
+
+	/// Parses an image as saved in the workspace.
+	fn from_saved(saved: &str) -> Result<Self, Box<dyn std::error::Error>> {
+		match saved.strip_prefix(REFERENCE_PREFIX) {
+			Some(hash) => Self::load(hash),
+			// Saved by older versions.
+			None => Self::from_data_url(saved),
+		}
+	}
+
+	/// Loads an image saved by `store`.
+	fn load(hash: &str) -> Result<Self, Box<dyn std::error::Error>> {
+		let row = select!(Option<StoredImage> "WHERE hash = " hash)?
+			.ok_or_else(|| format!("image {} is missing from the database", hash))?;
+		Self::from_bytes("image", row.bytes)
+	}
+
+	pub(crate) fn thumbnail(&self, ctx: &Context) -> Image<'static> {
+		ctx.include_bytes(self.uri.clone(), self.bytes.clone());
+		Image::from_uri(self.uri.clone()).max_height(80.0).maintain_aspect_ratio(true)
+	}
+}
+
+/// Saved as a reference to the bytes, which `store` puts in the database.
+impl Serialize for ImageAttachment {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_str(&format!("{}{}", REFERENCE_PREFIX, self.hash))
+	}
+}
+
+impl<'de> Deserialize<'de> for ImageAttachment {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		Self::from_saved(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+	}
+}
+
+/// Deserializes a message's images, leaving out any that can't be loaded, e.g. from another
+/// database, rather than failing the whole workspace.
+pub(crate) fn deserialize_images<'de, D: Deserializer<'de>>(
+	deserializer: D,
+) -> Result<Vec<ImageAttachment>, D::Error> {
+	let images = Vec::<String>::deserialize(deserializer)?;
+	Ok(
+		images
+			.iter()
+			.filter_map(|saved| ImageAttachment::from_saved(saved).map_err(|e| eprintln!("{}", e)).ok())
+			.collect(),
+	)
+}
+
+pub(crate) fn is_image_path(path: &Path) -> bool {
+	path
+		.extension()
+		.and_then(|e| e.to_str())
+		.is_some_and(|e| ["png", "jpg", "jpeg"].contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// Shows a row of thumbnails; returns the index of one whose remove button was clicked.
+pub(crate) fn thumbnails(ui: &mut Ui, images: &[ImageAttachment]) -> Option<usize> {
+	let mut remove = None;
+	ui.horizontal_wrapped(|ui| {
+		for (i, image) in images.iter().enumerate() {
+			ui.add(image.thumbnail(ui.ctx()));
+			if ui.small_button("✖").on_hover_text("remove image").clicked() {
+				remove = Some(i);
+			}
+		}
+	});
+	remove
+}
+
+/// Multimodal content for a user message: the text followed by each image.
+pub(crate) fn content_parts(
+	text: String,
+	images: &[ImageAttachment],
+) -> Vec<ChatCompletionRequestMessageContentPart> {
+	let mut parts = vec![ChatCompletionRequestMessageContentPartTextArgs::default()
+		.text(text)
+		.build()
+		.unwrap()
+		.into()];
+	parts.extend(images.iter().map(|image| {
+		ChatCompletionRequestMessageContentPartImageArgs::default()
+			.image_url(ImageUrlArgs::default().url(image.data_url()).build().unwrap())
+			.build()
+			.unwrap()
+			.into()
+	}));
+	parts
+}
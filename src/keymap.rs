@@ -0,0 +1,695 @@
+use super::*;
+use tokio::sync::broadcast::{self, error::TryRecvError};
+
+/// Where an action's chord is listened for.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Scope {
+	Global,
+	/// Only while a message editor has focus.
+	Editor,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Action {
+	NewWindow,
+	CloseWindow,
+	ReopenWindow,
+	Save,
+	PreviousWindow,
+	NextWindow,
+	PreviousPane,
+	NextPane,
+	CheatSheet,
+	Send,
+	AcceptCompletion,
+	PreviousMessage,
+	NextMessage,
+	CursorLeft,
+	CursorRight,
+	CursorUp,
+	CursorDown,
+	WordLeft,
+	WordRight,
+	LineStart,
+	LineEnd,
+	DeleteForward,
+	KillLine,
+}
+
+pub(crate) struct Spec {
+	pub(crate) action: Action,
+	/// Also the `Setting` key, after `keybinding_`.
+	pub(crate) name: &'static str,
+	pub(crate) scope: Scope,
+	pub(crate) description: &'static str,
+	/// Chords in the `default`, `vim` and `emacs` presets, as parsed by `parse`. Empty is unbound.
+	pub(crate) default: &'static str,
+	pub(crate) vim: Option<&'static str>,
+	pub(crate) emacs: Option<&'static str>,
+}
+
+const fn spec(action: Action, name: &'static str, scope: Scope, description: &'static str) -> Spec {
+	Spec { action, name, scope, description, default: "", vim: None, emacs: None }
+}
+
+/// Every action that can be bound, in cheat sheet order.
+pub(crate) const ACTIONS: &[Spec] = &[
+	Spec {
+		default: "Cmd+N",
+		emacs: Some("Cmd+Shift+N"),
+		..spec(Action::NewWindow, "new_window", Scope::Global, "Open a wheel window.")
+	},
+	Spec {
+		default: "Cmd+W",
+		..spec(Action::CloseWindow, "close_window", Scope::Global, "Close the wheel window.")
+	},
+	Spec {
+		default: "Cmd+Shift+T",
+		..spec(Action::ReopenWindow, "reopen_window", Scope::Global, "Reopen the last closed window.")
+	},
+	Spec {
+		default: "Cmd+S",
+		..spec(Action::Save, "save", Scope::Global, "Save the first message as a document.")
+	},
+	Spec {
+		default: "Cmd+OpenBracket",
+		..spec(
+			Action::PreviousWindow,
+			"previous_window",
+			Scope::Global,
+			"Focus the previous wheel window.",
+		)
+	},
+	Spec {
+		default: "Cmd+CloseBracket",
+		..spec(Action::NextWindow, "next_window", Scope::Global, "Focus the next wheel window.")
+	},
+	Spec {
+		default: "Ctrl+Shift+Tab",
+		..spec(Action::PreviousPane, "previous_pane", Scope::Global, "Switch to the previous tile.")
+	},
+	Spec {
+		default: "Ctrl+Tab",
+		..spec(Action::NextPane, "next_pane", Scope::Global, "Switch to the next tile.")
+	},
+	Spec {
+		default: "F1",
+		..spec(Action::CheatSheet, "cheat_sheet", Scope::Global, "Show or hide this list.")
+	},
+	Spec {
+		default: "Cmd+Enter",
+		..spec(Action::Send, "send", Scope::Editor, "Send the conversation up to here.")
+	},
+	Spec {
+		default: "Tab",
+		..spec(
+			Action::AcceptCompletion,
+			"accept_completion",
+			Scope::Editor,
+			"Accept a line of the completion.",
+		)
+	},
+	Spec {
+		default: "Alt+Up",
+		vim: Some("Alt+Shift+K"),
+		..spec(Action::PreviousMessage, "previous_message", Scope::Editor, "Focus the message above.")
+	},
+	Spec {
+		default: "Alt+Down",
+		vim: Some("Alt+Shift+J"),
+		..spec(Action::NextMessage, "next_message", Scope::Editor, "Focus the message below.")
+	},
+	Spec {
+		vim: Some("Alt+H"),
+		emacs: Some("Ctrl+B"),
+		..spec(Action::CursorLeft, "cursor_left", Scope::Editor, "Move back a character.")
+	},
+	Spec {
+		vim: Some("Alt+L"),
+		emacs: Some("Ctrl+F"),
+		..spec(Action::CursorRight, "cursor_right", Scope::Editor, "Move forward a character.")
+	},
+	Spec {
+		vim: Some("Alt+K"),
+		emacs: Some("Ctrl+P"),
+		..spec(Action::CursorUp, "cursor_up", Scope::Editor, "Move up a line.")
+	},
+	Spec {
+		vim: Some("Alt+J"),
+		emacs: Some("Ctrl+N"),
+		..spec(Action::CursorDown, "cursor_down", Scope::Editor, "Move down a line.")
+	},
+	Spec {
+		vim: Some("Alt+B"),
+		emacs: Some("Alt+B"),
+		..spec(Action::WordLeft, "word_left", Scope::Editor, "Move back a word.")
+	},
+	Spec {
+		vim: Some("Alt+W"),
+		emacs: Some("Alt+F"),
+		..spec(Action::WordRight, "word_right", Scope::Editor, "Move forward a word.")
+	},
+	Spec {
+		vim: Some("Alt+0"),
+		emacs: Some("Ctrl+A"),
+		..spec(Action::LineStart, "line_start", Scope::Editor, "Move to the start of the line.")
+	},
+	Spec {
+		vim: Some("Alt+4"),
+		emacs: Some("Ctrl+E"),
+		..spec(Action::LineEnd, "line_end", Scope::Editor, "Move to the end of the line.")
+	},
+	Spec {
+		vim: Some("Alt+X"),
+		emacs: Some("Ctrl+D"),
+		..spec(Action::DeleteForward, "delete_forward", Scope::Editor, "Delete the next character.")
+	},
+	Spec {
+		vim: Some("Alt+Shift+D"),
+		emacs: Some("Ctrl+K"),
+		..spec(Action::KillLine, "kill_line", Scope::Editor, "Delete to the end of the line.")
+	},
+];
+
+impl Spec {
+	fn preset_chord(&self, preset: &str) -> &'static str {
+		match preset {
+			"vim" => self.vim,
+			"emacs" => self.emacs,
+			_ => None,
+		}
+		.unwrap_or(self.default)
+	}
+
+	fn setting_key(&self) -> String {
+		format!("keybinding_{}", self.name)
+	}
+}
+
+/// Parses chords like `Cmd+Shift+T`, where `Cmd` is Command on macOS and Ctrl elsewhere.
+pub(crate) fn parse(chord: &str) -> Result<KeyboardShortcut, String> {
+	let (modifiers, key) = chord.rsplit_once('+').unwrap_or(("", chord));
+	let key = Key::from_name(key).ok_or_else(|| format!("unknown key {}", key))?;
+	let mut shortcut = KeyboardShortcut::new(Modifiers::NONE, key);
+	for modifier in modifiers.split('+').filter(|m| !m.is_empty()) {
+		shortcut.modifiers = shortcut.modifiers.plus(match modifier {
+			"Cmd" => Modifiers::COMMAND,
+			"Ctrl" => Modifiers::CTRL,
+			"Alt" => Modifiers::ALT,
+			"Shift" => Modifiers::SHIFT,
+			_ => Err(format!("unknown modifier {}", modifier))?,
+		});
+	}
+	Ok(shortcut)
+}
+
+/// The inverse of `parse`.
+pub(crate) fn format(shortcut: KeyboardShortcut) -> String {
+	let modifiers = shortcut.modifiers;
+	let names = [
+		(modifiers.command || modifiers.mac_cmd, "Cmd"),
+		(modifiers.ctrl && !modifiers.command, "Ctrl"),
+		(modifiers.alt, "Alt"),
+		(modifiers.shift, "Shift"),
+	];
+	let mut chord =
+		names.iter().filter(|(on, _)| *on).map(|(_, name)| format!("{}+", name)).collect::<String>();
+	chord.push_str(shortcut.logical_key.name());
+	chord
+}
+
+/// The modifiers actually held to press `shortcut` on this platform, for comparing chords.
+fn physical(shortcut: KeyboardShortcut) -> (bool, bool, bool, bool, Key) {
+	let m = shortcut.modifiers;
+	let cmd = m.command || m.mac_cmd;
+	if cfg!(target_os = "macos") {
+		(cmd, m.ctrl, m.alt, m.shift, shortcut.logical_key)
+	} else {
+		(false, m.ctrl || cmd, m.alt, m.shift, shortcut.logical_key)
+	}
+}
+
+/// Consumes a press of exactly `shortcut`, and the text it typed. Unlike
+/// `InputState::consume_shortcut`, extra Shift or Alt doesn't match, so `Cmd+N` doesn't fire for
+/// `Cmd+Shift+N`.
+fn consume_exact(i: &mut InputState, shortcut: KeyboardShortcut) -> bool {
+	let mut found = false;
+	let mut typed_by_match = false;
+	i.events.retain(|event| {
+		let is_match = matches!(
+			event,
+			egui::Event::Key { key, pressed: true, modifiers, .. }
+				if *key == shortcut.logical_key && modifiers.matches_exact(shortcut.modifiers)
+		);
+		// Keeps e.g. the `˙` that Alt+H types on macOS out of the editor.
+		let is_typed = typed_by_match && matches!(event, egui::Event::Text(_));
+		typed_by_match = is_match;
+		found |= is_match;
+		!is_match && !is_typed
+	});
+	found
+}
+
+/// The chord bound to each action: the `keymap_preset` setting's, unless rebound.
+pub(crate) struct Keymap {
+	preset: String,
+	bindings: Vec<(&'static Spec, Option<KeyboardShortcut>)>,
+	/// Whether the cheat sheet is shown.
+	pub(crate) open: bool,
+	/// The action whose new chord is the next key pressed.
+	recording: Option<Action>,
+	changes: broadcast::Receiver<&'static str>,
+}
+
+impl Default for Keymap {
+	fn default() -> Self {
+		let mut keymap = Self {
+			preset: String::new(),
+			bindings: Vec::new(),
+			open: false,
+			recording: None,
+			changes: settings::subscribe(),
+		};
+		keymap.load();
+		keymap
+	}
+}
+
+impl Keymap {
+	fn load(&mut self) {
+		self.preset = settings::get("keymap_preset");
+		self.bindings = ACTIONS
+			.iter()
+			.map(|spec| {
+				let setting = Setting::get(&spec.setting_key());
+				let chord =
+					if setting.rowid.is_some() { setting.value } else { spec.preset_chord(&self.preset).into() };
+				let shortcut = match chord.as_str() {
+					"" => None,
+					chord => parse(chord).inspect_err(|e| eprintln!("{}: {}", spec.name, e)).ok(),
+				};
+				(spec, shortcut)
+			})
+			.collect();
+	}
+
+	/// Reloads if the preset was changed in settings.
+	pub(crate) fn refresh(&mut self) {
+		let mut changed = false;
+		loop {
+			match self.changes.try_recv() {
+				Ok(key) => changed |= key == "keymap_preset",
+				Err(TryRecvError::Lagged(_)) => changed = true,
+				Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+			}
+		}
+		if changed {
+			self.load();
+		}
+	}
+
+	pub(crate) fn shortcut(&self, action: Action) -> Option<KeyboardShortcut> {
+		self.bindings.iter().find(|(spec, _)| spec.action == action).and_then(|(_, shortcut)| *shortcut)
+	}
+
+	/// How to press `action`, for labels and hover text.
+	pub(crate) fn hint(&self, ctx: &Context, action: Action) -> String {
+		self.shortcut(action).map_or_else(|| "unbound".into(), |shortcut| ctx.format_shortcut(&shortcut))
+	}
+
+	/// Consumes the chords of `scope`'s actions pressed this frame.
+	pub(crate) fn pressed(&self, i: &mut InputState, scope: Scope) -> Vec<Action> {
+		if self.recording.is_some() {
+			return Vec::new();
+		}
+		self
+			.bindings
+			.iter()
+			.filter(|(spec, _)| spec.scope == scope)
+			.filter_map(|(spec, shortcut)| Some((spec.action, (*shortcut)?)))
+			.filter(|&(_, shortcut)| consume_exact(i, shortcut))
+			.map(|(action, _)| action)
+			.collect()
+	}
+
+	/// Pairs of actions bound to the same chord. Global chords are consumed first, so they also
+	/// clash with editor ones.
+	pub(crate) fn conflicts(&self) -> Vec<(&'static str, &'static str)> {
+		let mut conflicts = Vec::new();
+		for (i, (a, a_shortcut)) in self.bindings.iter().enumerate() {
+			for (b, b_shortcut) in &self.bindings[i + 1..] {
+				if let (Some(x), Some(y)) = (a_shortcut, b_shortcut)
+					&& physical(*x) == physical(*y)
+				{
+					conflicts.push((a.name, b.name));
+				}
+			}
+		}
+		conflicts
+	}
+
+	/// Stores `shortcut` for `action`, or clears the override if it's `None`.
+	fn rebind(&mut self, spec: &Spec, shortcut: Option<Option<KeyboardShortcut>>) {
+		let key = spec.setting_key();
+		let result = match shortcut {
+			Some(shortcut) => {
+				let mut setting = Setting::get(&key);
+				setting.value = shortcut.map(format).unwrap_or_default();
+				setting.save();
+				Ok(())
+			}
+			None => execute!("DELETE FROM setting WHERE key = ?", key).map(|_| ()),
+		};
+		if let Err(e) = result {
+			eprintln!("{}", e);
+		}
+		self.load();
+	}
+
+	/// Takes the next key press as the chord for the action being recorded; Escape cancels.
+	pub(crate) fn record(&mut self, ctx: &Context) {
+		let Some(action) = self.recording else {
+			return;
+		};
+		let pressed = ctx.input_mut(|i| {
+			let mut pressed = None;
+			i.events.retain(|event| match event {
+				egui::Event::Key { key, pressed: true, modifiers, .. } => {
+					pressed.get_or_insert(KeyboardShortcut::new(*modifiers, *key));
+					false
+				}
+				// Keeps the keys from also typing into an editor.
+				egui::Event::Text(_) => false,
+				_ => true,
+			});
+			pressed
+		});
+		let Some(mut shortcut) = pressed else {
+			return;
+		};
+		self.recording = None;
+		if shortcut.logical_key == Key::Escape && shortcut.modifiers.is_none() {
+			return;
+		}
+		// Stored as `Cmd` so it carries over between platforms.
+		let m = &mut shortcut.modifiers;
+		if m.command {
+			(m.ctrl, m.mac_cmd) = (false, false);
+		}
+		let spec = ACTIONS.iter().find(|spec| spec.action == action).unwrap();
+		self.rebind(spec, Some(Some(shortcut)));
+	}
+
+	/// The cheat sheet, where chords can also be rebound.
+	pub(crate) fn show(&mut self, ctx: &Context) {
+		let mut open = self.open;
+		egui::Window::new("keyboard shortcuts").open(&mut open).show(ctx, |ui| {
+			ui.label(format!("preset: {} (change it in settings)", self.preset));
+
+			for (a, b) in self.conflicts() {
+				ui.colored_label(ui.visuals().error_fg_color, format!("{} and {} have the same chord", a, b));
+			}
+
+			let mut rebind = None;
+			ScrollArea::vertical().show(ui, |ui| {
+				for (scope, heading) in [(Scope::Global, "anywhere"), (Scope::Editor, "in a message editor")] {
+					ui.heading(heading);
+					Grid::new(heading).num_columns(3).striped(true).show(ui, |ui| {
+						for &(spec, shortcut) in self.bindings.iter().filter(|(spec, _)| spec.scope == scope) {
+							ui.label(spec.description);
+							let chord = if self.recording == Some(spec.action) {
+								"press a key…".to_owned()
+							} else {
+								shortcut.map_or_else(|| "unbound".into(), |s| ctx.format_shortcut(&s))
+							};
+							if ui.button(chord).on_hover_text("click, then press the new chord").clicked() {
+								self.recording = Some(spec.action);
+							}
+							ui.horizontal(|ui| {
+								if ui.small_button("clear").clicked() {
+									rebind = Some((spec, Some(None)));
+								}
+								if ui.small_button("reset").on_hover_text("use the preset's chord").clicked() {
+									rebind = Some((spec, None));
+								}
+							});
+							ui.end_row();
+						}
+					});
+				}
+			});
+			if let Some((spec, shortcut)) = rebind {
+				self.rebind(spec, shortcut);
+			}
+		});
+		self.open = open;
+		if !open {
+			self.recording = None;
+		}
+	}
+}
+
+/// Applies an editing action to `text` with the cursor before char `cursor`; returns where the
+/// cursor ends up.
+pub(crate) fn edit(action: Action, text: &mut String, cursor: usize) -> usize {
+	let chars = text.chars().collect::<Vec<_>>();
+	let cursor = cursor.min(chars.len());
+	let line_start = |i: usize| chars[..i].iter().rposition(|&c| c == '\n').map_or(0, |n| n + 1);
+	let line_end =
+		|i: usize| chars[i..].iter().position(|&c| c == '\n').map_or(chars.len(), |n| i + n);
+	let is_word = |i: usize| chars[i].is_alphanumeric() || chars[i] == '_';
+	let (start, end) = (line_start(cursor), line_end(cursor));
+	let mut remove = |range: std::ops::Range<usize>| {
+		*text = chars[..range.start].iter().chain(&chars[range.end..]).collect();
+	};
+
+	match action {
+		Action::CursorLeft => cursor.saturating_sub(1),
+		Action::CursorRight => (cursor + 1).min(chars.len()),
+		Action::CursorUp if start > 0 => {
+			let above = line_start(start - 1);
+			(above + cursor - start).min(start - 1)
+		}
+		Action::CursorDown if end < chars.len() => (end + 1 + cursor - start).min(line_end(end + 1)),
+		Action::WordLeft => {
+			let mut i = cursor;
+			while i > 0 && !is_word(i - 1) {
+				i -= 1;
+			}
+			while i > 0 && is_word(i - 1) {
+				i -= 1;
+			}
+			i
+		}
+		Action::WordRight => {
+			let mut i = cursor;
+			while i < chars.len() && !is_word(i) {
+				i += 1;
+			}
+			while i < chars.len() && is_word(i) {
+				i += 1;
+			}
+			i
+		}
+		Action::LineStart => start,
+		Action::LineEnd => end,
+		Action::DeleteForward if cursor < chars.len() => {
+			remove(cursor..cursor + 1);
+			cursor
+		}
+		// At the end of a line, joins it with the next one.
+		Action::KillLine if cursor < chars.len() => {
+			remove(cursor..if end == cursor { end + 1 } else { end });
+			cursor
+		}
+		_ => cursor,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn keymap(chords: &[(Action, &str)]) -> Keymap {
+		let bindings = chords
+			.iter()
+			.map(|&(action, chord)| {
+				(ACTIONS.iter().find(|s| s.action == action).unwrap(), Some(parse(chord).unwrap()))
+			})
+			.collect();
+		Keymap {
+			preset: String::new(),
+			bindings,
+			open: false,
+			recording: None,
+			changes: settings::subscribe(),
+		}
+	}
+
+	fn preset(preset: &str) -> Keymap {
+		let bindings = ACTIONS.iter().map(|spec| (spec, parse(spec.preset_chord(preset)).ok())).collect();
+		Keymap {
+			preset: preset.to_owned(),
+			bindings,
+			open: false,
+			recording: None,
+			changes: settings::subscribe(),
+		}
+	}
+
+	#[test]
+	fn preset_chords_round_trip() {
+		for spec in ACTIONS {
+			for chord in [Some(spec.default), spec.vim, spec.emacs].into_iter().flatten() {
+				if !chord.is_empty() {
+					assert_eq!(format(parse(chord).unwrap()), chord);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn format_orders_modifiers() {
+		assert_eq!(format(parse("Shift+Alt+Cmd+N").unwrap()), "Cmd+Alt+Shift+N");
+		assert_eq!(format(parse("N").unwrap()), "N");
+	}
+
+	#[test]
+	fn rejects_unknown_names() {
+		assert_eq!(parse("Cmd+Nope").unwrap_err(), "unknown key Nope");
+		assert_eq!(parse("Hyper+N").unwrap_err(), "unknown modifier Hyper");
+	}
+
+	fn key(key: Key, modifiers: Modifiers) -> egui::Event {
+		egui::Event::Key { key, physical_key: None, pressed: true, repeat: false, modifiers }
+	}
+
+	#[test]
+	fn pressed_consumes_the_text_a_chord_typed() {
+		let keymap = keymap(&[(Action::CursorLeft, "Alt+H")]);
+		let mut input = InputState::default();
+		input.events = vec![
+			key(Key::H, Modifiers::ALT),
+			egui::Event::Text("˙".into()),
+			key(Key::H, Modifiers::NONE),
+			egui::Event::Text("h".into()),
+		];
+		assert_eq!(keymap.pressed(&mut input, Scope::Editor), [Action::CursorLeft]);
+		assert_eq!(input.events, [key(Key::H, Modifiers::NONE), egui::Event::Text("h".into())]);
+	}
+
+	#[test]
+	fn same_chord_conflicts() {
+		let keymap = keymap(&[(Action::NewWindow, "Cmd+K"), (Action::Save, "Cmd+K")]);
+		assert_eq!(keymap.conflicts(), [("new_window", "save")]);
+	}
+
+	#[test]
+	fn extra_modifier_does_not_conflict() {
+		let keymap = keymap(&[(Action::NewWindow, "Cmd+K"), (Action::Save, "Cmd+Shift+K")]);
+		assert!(keymap.conflicts().is_empty());
+	}
+
+	#[test]
+	fn cmd_and_ctrl_conflict_only_off_macos() {
+		let keymap = keymap(&[(Action::NewWindow, "Cmd+K"), (Action::Save, "Ctrl+K")]);
+		if cfg!(target_os = "macos") {
+			assert!(keymap.conflicts().is_empty());
+		} else {
+			assert_eq!(keymap.conflicts(), [("new_window", "save")]);
+		}
+	}
+
+	#[test]
+	fn presets_have_no_conflicts() {
+		for name in ["default", "vim", "emacs"] {
+			assert!(preset(name).conflicts().is_empty(), "{}", name);
+		}
+	}
+
+	fn edited(action: Action, text: &str, cursor: usize) -> (String, usize) {
+		let mut text = text.to_owned();
+		let cursor = edit(action, &mut text, cursor);
+		(text, cursor)
+	}
+
+	fn moved(action: Action, text: &str, cursor: usize) -> usize {
+		let (edited, cursor) = edited(action, text, cursor);
+		assert_eq!(edited, text);
+		cursor
+	}
+
+	#[test]
+	fn cursor_left_and_right_stop_at_the_ends() {
+		assert_eq!(moved(Action::CursorLeft, "ab\ncd", 0), 0);
+		assert_eq!(moved(Action::CursorLeft, "ab\ncd", 3), 2);
+		assert_eq!(moved(Action::CursorRight, "ab\ncd", 2), 3);
+		assert_eq!(moved(Action::CursorRight, "ab\ncd", 5), 5);
+		assert_eq!(moved(Action::CursorRight, "ab", 10), 2);
+	}
+
+	#[test]
+	fn cursor_up_and_down_keep_the_column() {
+		assert_eq!(moved(Action::CursorUp, "ab\ncd", 4), 1);
+		assert_eq!(moved(Action::CursorDown, "ab\ncd", 1), 4);
+		// Onto a shorter line, the cursor stops at its end.
+		assert_eq!(moved(Action::CursorUp, "a\nlong", 6), 1);
+		assert_eq!(moved(Action::CursorDown, "long\na", 4), 6);
+	}
+
+	#[test]
+	fn cursor_up_and_down_stay_on_the_first_and_last_lines() {
+		assert_eq!(moved(Action::CursorUp, "ab\ncd", 1), 1);
+		assert_eq!(moved(Action::CursorDown, "ab\ncd", 4), 4);
+		assert_eq!(moved(Action::CursorUp, "", 0), 0);
+		assert_eq!(moved(Action::CursorDown, "", 0), 0);
+	}
+
+	#[test]
+	fn word_movement_skips_punctuation_and_stops_at_the_ends() {
+		assert_eq!(moved(Action::WordLeft, "foo, bar", 8), 5);
+		assert_eq!(moved(Action::WordLeft, "foo, bar", 5), 0);
+		assert_eq!(moved(Action::WordLeft, "foo, bar", 0), 0);
+		assert_eq!(moved(Action::WordRight, "foo, bar", 0), 3);
+		assert_eq!(moved(Action::WordRight, "foo, bar", 3), 8);
+		assert_eq!(moved(Action::WordRight, "foo, bar", 8), 8);
+		assert_eq!(moved(Action::WordRight, "snake_case", 0), 10);
+	}
+
+	#[test]
+	fn line_start_and_end() {
+		assert_eq!(moved(Action::LineStart, "ab\ncd", 4), 3);
+		assert_eq!(moved(Action::LineEnd, "ab\ncd", 4), 5);
+		assert_eq!(moved(Action::LineStart, "ab\ncd", 0), 0);
+		assert_eq!(moved(Action::LineEnd, "ab\ncd", 0), 2);
+		// Just before a newline is the end of its line.
+		assert_eq!(moved(Action::LineStart, "ab\ncd", 2), 0);
+		assert_eq!(moved(Action::LineEnd, "ab\ncd", 2), 2);
+	}
+
+	#[test]
+	fn delete_forward() {
+		assert_eq!(edited(Action::DeleteForward, "ab\ncd", 1), ("a\ncd".into(), 1));
+		assert_eq!(edited(Action::DeleteForward, "ab\ncd", 2), ("abcd".into(), 2));
+		assert_eq!(edited(Action::DeleteForward, "ab\ncd", 5), ("ab\ncd".into(), 5));
+	}
+
+	#[test]
+	fn kill_line_deletes_to_the_end_or_joins_lines() {
+		assert_eq!(edited(Action::KillLine, "ab\ncd", 0), ("\ncd".into(), 0));
+		assert_eq!(edited(Action::KillLine, "ab\ncd", 2), ("abcd".into(), 2));
+		assert_eq!(edited(Action::KillLine, "ab\ncd", 4), ("ab\nc".into(), 4));
+		assert_eq!(edited(Action::KillLine, "ab\ncd", 5), ("ab\ncd".into(), 5));
+	}
+
+	#[test]
+	fn non_editing_actions_leave_the_text() {
+		assert_eq!(edited(Action::Send, "ab", 1), ("ab".into(), 1));
+	}
+
+	#[test]
+	fn counts_chars_not_bytes() {
+		assert_eq!(edited(Action::DeleteForward, "é🙂x", 1), ("éx".into(), 1));
+		assert_eq!(moved(Action::LineEnd, "é🙂x", 0), 3);
+	}
+}
@@ -0,0 +1,230 @@
+use super::*;
+use egui_tiles::{SimplificationOptions, Tile, TileId, Tiles, Tree, UiResponse};
+use tokio::sync::broadcast::{self, error::TryRecvError};
+
+/// What a tile shows. Sorted in the order Ctrl-Tab visits them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub(crate) enum Pane {
+	Wheel(WindowId),
+	Documents,
+	Search,
+	Settings,
+	Dataset,
+}
+
+/// Tabs and splits in the central area. Every open wheel window and tool window has a tile.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct Layout {
+	tree: Tree<Pane>,
+	/// The pane last clicked or switched to.
+	#[serde(skip)]
+	current: Option<Pane>,
+	/// The `layout` setting, or `None` until it's read or after it changes.
+	#[serde(skip)]
+	tiled: Option<bool>,
+	#[serde(skip, default = "settings::subscribe")]
+	changes: broadcast::Receiver<&'static str>,
+}
+
+impl Default for Layout {
+	fn default() -> Self {
+		Self { tree: Tree::empty("layout"), current: None, tiled: None, changes: settings::subscribe() }
+	}
+}
+
+/// Everything panes draw, borrowed from the app for one frame.
+pub(crate) struct Panes<'a> {
+	pub(crate) windows: &'a mut [WheelWindow],
+	pub(crate) store: &'a mut state::Store,
+	pub(crate) dropped_files: &'a [DroppedFile],
+	pub(crate) documents: &'a mut library::DocumentsWindow,
+	pub(crate) search: &'a mut library::SearchWindow,
+	pub(crate) settings: &'a mut settings::SettingsWindow,
+	pub(crate) secrets_ui: &'a mut secrets::SecretsUi,
+	pub(crate) dataset: &'a mut dataset::DatasetWindow,
+}
+
+impl Panes<'_> {
+	fn is_open(&self, pane: Pane) -> bool {
+		match pane {
+			Pane::Wheel(id) => self.windows.iter().any(|w| w.id == id && w.open),
+			Pane::Documents => self.documents.open,
+			Pane::Search => self.search.open,
+			Pane::Settings => self.settings.open,
+			Pane::Dataset => self.dataset.open,
+		}
+	}
+
+	fn close(&mut self, pane: Pane) {
+		match pane {
+			Pane::Wheel(id) => {
+				if let Some(window) = self.windows.iter_mut().find(|w| w.id == id) {
+					window.open = false;
+				}
+			}
+			Pane::Documents => self.documents.open = false,
+			Pane::Search => self.search.open = false,
+			Pane::Settings => self.settings.open = false,
+			Pane::Dataset => self.dataset.open = false,
+		}
+	}
+}
+
+/// Tracks which pane was clicked while the tree is drawn.
+struct Behavior<'a, 'b> {
+	panes: &'a mut Panes<'b>,
+	clicked: Option<Pane>,
+}
+
+impl egui_tiles::Behavior<Pane> for Behavior<'_, '_> {
+	fn pane_ui(&mut self, ui: &mut Ui, _tile_id: TileId, pane: &mut Pane) -> UiResponse {
+		if ui.ui_contains_pointer() && ui.input(|i| i.pointer.any_pressed()) {
+			self.clicked = Some(*pane);
+		}
+
+		let panes = &mut *self.panes;
+		match *pane {
+			Pane::Wheel(id) => {
+				if let Some(window) = panes.windows.iter_mut().find(|w| w.id == id) {
+					window.ui(ui, panes.store, panes.dropped_files);
+				}
+			}
+			Pane::Documents => panes.documents.ui(ui, panes.store),
+			Pane::Search => panes.search.ui(ui, panes.store),
+			Pane::Settings => panes.settings.ui(ui, panes.secrets_ui),
+			Pane::Dataset => panes.dataset.ui(ui),
+		}
+
+		UiResponse::None
+	}
+
+	fn tab_title_for_pane(&mut self, pane: &Pane) -> WidgetText {
+		match pane {
+			Pane::Wheel(id) => format!("wheel {}", id).into(),
+			Pane::Documents => "documents".into(),
+			Pane::Search => "search".into(),
+			Pane::Settings => "settings".into(),
+			Pane::Dataset => "dataset".into(),
+		}
+	}
+
+	fn is_tab_closable(&self, _tiles: &Tiles<Pane>, _tile_id: TileId) -> bool {
+		true
+	}
+
+	/// Closing a wheel window's tab closes the window, so it can be reopened.
+	fn on_tab_close(&mut self, tiles: &mut Tiles<Pane>, tile_id: TileId) -> bool {
+		if let Some(Tile::Pane(pane)) = tiles.get(tile_id) {
+			self.panes.close(*pane);
+		}
+		true
+	}
+
+	fn simplification_options(&self) -> SimplificationOptions {
+		SimplificationOptions { all_panes_must_have_tabs: true, ..Default::default() }
+	}
+}
+
+impl Layout {
+	/// Whether the `layout` setting puts windows in tiles rather than floating windows.
+	pub(crate) fn tiled(&mut self) -> bool {
+		loop {
+			match self.changes.try_recv() {
+				Ok("layout") | Err(TryRecvError::Lagged(_)) => self.tiled = None,
+				Ok(_) => {}
+				Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+			}
+		}
+		*self.tiled.get_or_insert_with(|| settings::get("layout") == "tiled")
+	}
+
+	fn panes(&self) -> Vec<(TileId, Pane)> {
+		let mut panes = self
+			.tree
+			.tiles
+			.iter()
+			.filter_map(|(&id, tile)| match tile {
+				Tile::Pane(pane) => Some((id, *pane)),
+				Tile::Container(_) => None,
+			})
+			.collect::<Vec<_>>();
+		panes.sort_by_key(|&(_, pane)| pane);
+		panes
+	}
+
+	/// Adds tiles for newly opened windows, as tabs of the root, and removes tiles of closed ones.
+	fn sync(&mut self, panes: &Panes) {
+		for (tile_id, pane) in self.panes() {
+			if !panes.is_open(pane) {
+				self.tree.remove_recursively(tile_id);
+			}
+		}
+
+		let open = panes
+			.windows
+			.iter()
+			.filter(|w| w.open)
+			.map(|w| Pane::Wheel(w.id))
+			.chain([Pane::Documents, Pane::Search, Pane::Settings, Pane::Dataset])
+			.filter(|&pane| panes.is_open(pane));
+		for pane in open {
+			if self.find(pane).is_none() {
+				let tile_id = self.tree.tiles.insert_pane(pane);
+				self.add_to_root(tile_id);
+				self.reveal(pane);
+			}
+		}
+	}
+
+	fn add_to_root(&mut self, tile_id: TileId) {
+		match self.tree.root {
+			None => self.tree.root = Some(self.tree.tiles.insert_tab_tile(vec![tile_id])),
+			Some(root) => match self.tree.tiles.get_mut(root) {
+				Some(Tile::Container(container)) => container.add_child(tile_id),
+				_ => self.tree.root = Some(self.tree.tiles.insert_tab_tile(vec![root, tile_id])),
+			},
+		}
+	}
+
+	fn find(&self, pane: Pane) -> Option<TileId> {
+		self.panes().into_iter().find(|&(_, p)| p == pane).map(|(id, _)| id)
+	}
+
+	/// Switches to the tab showing `pane`.
+	pub(crate) fn reveal(&mut self, pane: Pane) {
+		if let Some(tile_id) = self.find(pane) {
+			self.tree.make_active(|id, _| id == tile_id);
+			self.current = Some(pane);
+		}
+	}
+
+	/// Switches to the pane `offset` panes away from the current one, wrapping around, and
+	/// focuses its editor if it's a wheel window.
+	pub(crate) fn cycle(&mut self, offset: isize, windows: &[WheelWindow], store: &mut state::Store) {
+		let panes = self.panes();
+		if panes.is_empty() {
+			return;
+		}
+		let current = self.current.and_then(|pane| panes.iter().position(|&(_, p)| p == pane));
+		let len = panes.len() as isize;
+		let next = current.map_or(0, |i| (i as isize + offset).rem_euclid(len) as usize);
+		let pane = panes[next].1;
+		self.reveal(pane);
+		if let Pane::Wheel(id) = pane {
+			store.active = Some(id);
+			store.focus = windows.iter().find(|w| w.id == id).and_then(WheelWindow::focused_message);
+		}
+	}
+
+	pub(crate) fn ui(&mut self, ctx: &Context, mut panes: Panes) {
+		self.sync(&panes);
+
+		CentralPanel::default().show(ctx, |ui| {
+			let mut behavior = Behavior { panes: &mut panes, clicked: None };
+			self.tree.ui(&mut behavior, ui);
+			if let Some(pane) = behavior.clicked {
+				self.current = Some(pane);
+			}
+		});
+	}
+}
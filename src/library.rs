@@ -0,0 +1,101 @@
+use super::*;
+
+/// Opens `content` as the prompt of a new wheel window.
+fn open(store: &mut state::Store, content: &str) {
+	store.open(WheelWindow {
+		messages: vec![ChatMessage::new(User, content.to_owned())],
+		..WheelWindow::new()
+	});
+}
+
+/// Saved documents, newest first.
+#[derive(Default)]
+pub(crate) struct DocumentsWindow {
+	pub(crate) open: bool,
+	/// Loaded when first shown, and on reload.
+	documents: Option<Vec<Document>>,
+}
+
+impl DocumentsWindow {
+	pub(crate) fn show(&mut self, ctx: &Context, store: &mut state::Store) {
+		let mut open = self.open;
+		egui::Window::new("documents").open(&mut open).show(ctx, |ui| self.ui(ui, store));
+		self.open = open;
+	}
+
+	pub(crate) fn ui(&mut self, ui: &mut Ui, store: &mut state::Store) {
+		if ui.button("⟳ reload").clicked() {
+			self.documents = None;
+		}
+
+		let documents = self.documents.get_or_insert_with(|| {
+			select!(Vec<Document> "ORDER BY timestamp_ms DESC").unwrap_or_else(|e| {
+				eprintln!("{}", e);
+				Vec::new()
+			})
+		});
+
+		ScrollArea::vertical().id_source("documents").show(ui, |ui| {
+			for document in documents.iter() {
+				ui.horizontal(|ui| {
+					if ui.small_button("open").clicked() {
+						open(store, &document.content);
+					}
+					ui.label(RichText::new(&document.title).strong());
+					ui.label(search::first_line(&document.content));
+				});
+			}
+		});
+	}
+}
+
+/// Past prompts and saved documents containing a query.
+#[derive(Default)]
+pub(crate) struct SearchWindow {
+	pub(crate) open: bool,
+	query: String,
+	results: Vec<search::SearchResult>,
+}
+
+impl SearchWindow {
+	/// Results of each kind, at most.
+	const LIMIT: i64 = 50;
+
+	pub(crate) fn show(&mut self, ctx: &Context, store: &mut state::Store) {
+		let mut open = self.open;
+		egui::Window::new("search").open(&mut open).show(ctx, |ui| self.ui(ui, store));
+		self.open = open;
+	}
+
+	pub(crate) fn ui(&mut self, ui: &mut Ui, store: &mut state::Store) {
+		let response =
+			ui.add(TextEdit::singleline(&mut self.query).hint_text("search prompts and documents"));
+		if response.changed() {
+			self.search();
+		}
+
+		ScrollArea::vertical().id_source("search").show(ui, |ui| {
+			for result in &self.results {
+				ui.horizontal(|ui| {
+					if ui.small_button("open").clicked() {
+						open(store, result.content());
+					}
+					ui.label(RichText::new(result.kind()).weak());
+					ui.label(search::first_line(result.content()));
+				});
+			}
+		});
+	}
+
+	fn search(&mut self) {
+		self.results.clear();
+		if self.query.trim().is_empty() {
+			return;
+		}
+
+		match search::search(&self.query, Self::LIMIT) {
+			Ok(results) => self.results = results,
+			Err(e) => eprintln!("{}", e),
+		}
+	}
+}
@@ -6,12 +6,17 @@ use async_openai::types::Role::{self, *};
 use async_openai::types::{
 	ChatCompletionRequestMessage, ChatCompletionToolArgs, ChatCompletionToolType, FunctionObjectArgs,
 };
+use context::ContextAttachment;
 use egui::text::LayoutJob;
 use egui::*;
 use futures::channel::mpsc::{self, Sender};
+use images::ImageAttachment;
+use keymap::Action;
 use once_cell::sync::Lazy;
+use params::GenerationParams;
 use poll_promise::Promise;
 use serde::{Deserialize, Serialize};
+use state::{Event, MessageAction, MessageId, WindowId};
 use std::collections::HashMap;
 use std::sync::Mutex;
 use std::time::Duration;
@@ -19,38 +24,453 @@ use stream_cancel::{StreamExt as _, Trigger, Tripwire};
 use turbosql::*;
 
 mod audiofile;
+mod cli;
+mod code;
+mod compare;
+mod context;
+mod dataset;
+mod images;
+mod keymap;
+mod layout;
+mod library;
+mod params;
+mod relay;
+mod search;
+mod secrets;
 mod self_update;
+mod server;
 // mod session;
+mod settings;
+mod state;
+mod theme;
+mod transcript;
 
-static TOKENIZER: Lazy<Mutex<tiktoken_rs::CoreBPE>> =
-	Lazy::new(|| Mutex::new(tiktoken_rs::o200k_base().unwrap()));
-static COMPLETION: Lazy<Mutex<String>> = Lazy::new(Default::default);
-static COMPLETION_PROMPT: Lazy<Mutex<String>> = Lazy::new(|| Mutex::new(String::from("")));
+static TOKENIZER: Lazy<tiktoken_rs::CoreBPE> = Lazy::new(|| tiktoken_rs::o200k_base().unwrap());
 
-#[derive(Clone)]
+fn token_count(text: &str) -> usize {
+	TOKENIZER.encode_with_special_tokens(text).len()
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 struct ChatMessage {
+	id: MessageId,
 	role: Role,
 	content: String,
 	token_count: usize,
+	#[serde(deserialize_with = "images::deserialize_images")]
+	images: Vec<ImageAttachment>,
+	/// Kept, and sent, when the conversation is sent again from a message above it.
+	#[serde(default)]
+	pinned: bool,
+}
+
+impl ChatMessage {
+	fn new(role: Role, content: String) -> Self {
+		Self {
+			id: MessageId::new(),
+			role,
+			token_count: token_count(&content),
+			content,
+			images: Vec::new(),
+			pinned: false,
+		}
+	}
 }
 
+#[derive(Deserialize, Serialize)]
 struct WheelWindow {
+	id: WindowId,
 	open: bool,
+	#[serde(skip)]
 	request_close: bool,
 	messages: Vec<ChatMessage>,
+	params: GenerationParams,
+	context: Vec<ContextAttachment>,
+	/// The message whose editor last had focus.
+	focused: Option<MessageId>,
+	/// The assistant message being streamed into; dropping the trigger cancels the stream.
+	#[serde(skip)]
+	stream: Option<(MessageId, Trigger)>,
+	/// Why the last completion failed.
+	#[serde(skip)]
+	error: Option<String>,
+	/// The window and message this window was forked from.
+	#[serde(default)]
+	parent: Option<(WindowId, MessageId)>,
+	/// Messages before each change made with the message buttons or by sending, most recent last.
+	#[serde(skip)]
+	undo: Vec<Vec<ChatMessage>>,
 }
 
 impl Default for WheelWindow {
 	fn default() -> Self {
 		Self {
+			id: WindowId::new(),
 			open: true,
 			request_close: false,
-			messages: vec![ChatMessage { role: User, content: String::new(), token_count: 0 }],
+			messages: vec![ChatMessage::new(User, String::new())],
+			params: GenerationParams::default(),
+			context: Vec::new(),
+			focused: None,
+			stream: None,
+			error: None,
+			parent: None,
+			undo: Vec::new(),
 		}
 	}
 }
 
-static WHEEL_WINDOWS: Lazy<Mutex<Vec<WheelWindow>>> = Lazy::new(Default::default);
+impl WheelWindow {
+	/// A new window with the default parameters from settings.
+	fn new() -> Self {
+		Self { params: GenerationParams::from_settings(), ..Default::default() }
+	}
+
+	/// The window's contents, drawn in a floating window or a tile.
+	fn ui(&mut self, ui: &mut Ui, store: &mut state::Store, dropped_files: &[DroppedFile]) {
+		if !dropped_files.is_empty() && ui.ui_contains_pointer() {
+			for file in dropped_files {
+				match &file.path {
+					Some(path) if !images::is_image_path(path) => {
+						self.context.push(ContextAttachment::new(path.clone()))
+					}
+					_ => {
+						if let Some(message) = self.messages.iter_mut().rev().find(|m| m.role == User) {
+							message.images.extend(ImageAttachment::from_dropped_file(file));
+						}
+					}
+				}
+			}
+		}
+
+		if let Some((window, message)) = self.parent
+			&& ui.link(format!("⑂ forked from wheel {}", window)).clicked()
+		{
+			store.navigate_to(window, message);
+		}
+
+		CollapsingHeader::new(&self.params.model)
+			.id_source(("params", self.id))
+			.show(ui, |ui| self.params.ui(ui));
+
+		let context_tokens = context::tokens(&mut self.context);
+		CollapsingHeader::new(format!("context ({} tokens)", context_tokens))
+			.id_source(("context", self.id))
+			.show(ui, |ui| context::ui(ui, &mut self.context));
+
+		ScrollArea::vertical().show(ui, |ui| {
+			if ui.button("copy all to clipboard").clicked() {
+				let mut text = "\n".to_string();
+
+				for entry in self.messages.iter() {
+					text.push_str(&format!("[{}]: {}\n", entry.role, entry.content));
+				}
+
+				ui.output_mut(|o| o.copied_text = text);
+			}
+			if ui
+				.add_enabled(!self.undo.is_empty(), Button::new("↶ undo"))
+				.on_hover_text("undo the last change to the messages")
+				.clicked()
+			{
+				store.focus = self.undo();
+			}
+			ui.menu_button("export…", |ui| {
+				for format in transcript::Format::EXPORT {
+					if ui.button(format.label()).clicked() {
+						ui.close_menu();
+						transcript::save(format, &self.messages, &format!("wheel {}", self.id));
+					}
+				}
+			});
+			if ui.button("add to dataset").clicked() {
+				if let Err(e) = dataset::add(&self.messages) {
+					eprintln!("{}", e);
+				}
+			}
+			if ui.button("compare models").clicked() {
+				let mut messages = self.messages.clone();
+				while messages.last().is_some_and(|m| m.content.is_empty()) {
+					messages.pop();
+				}
+				context::reread(&mut self.context);
+				compare::open(store, messages, context::snapshot(&mut self.context), self.params.clone());
+			}
+			let mut do_it = false;
+			let mut do_it_j = 9999;
+			let mut message_action = None;
+			let mut regenerate = None;
+			let mut fork = None;
+			let mut total_tokens = context_tokens;
+			let message_ids = self.messages.iter().map(|m| m.id).collect::<Vec<_>>();
+			for (j, entry) in self.messages.iter_mut().enumerate() {
+				let id = entry.id.editor_id();
+				let editor_has_focus = ui.ctx().memory(|m| m.has_focus(id));
+
+				if editor_has_focus {
+					self.focused = Some(entry.id);
+					store.active = Some(self.id);
+
+					let actions = ui.input_mut(|i| store.keymap.pressed(i, keymap::Scope::Editor));
+					for action in actions {
+						match action {
+							Action::PreviousMessage | Action::NextMessage => {
+								let k = if action == Action::NextMessage { j + 1 } else { j.wrapping_sub(1) };
+								if let Some(&message) = message_ids.get(k) {
+									store.focus = Some(message);
+								}
+							}
+							Action::AcceptCompletion => {
+								entry.content.push_str(store.completion.split('\n').next().unwrap());
+								store.completion.clear();
+								if let Some(mut state) = egui::TextEdit::load_state(ui.ctx(), id) {
+									let ccursor = egui::text::CCursor::new(entry.content.chars().count());
+									state.cursor.set_char_range(Some(egui::text::CCursorRange::one(ccursor)));
+									state.store(ui.ctx(), id);
+									// ui.ctx().memory().request_focus(text_edit_id); // give focus back to the `TextEdit`.
+								}
+							}
+							Action::Send => {
+								store.completion.clear();
+								do_it = true;
+								do_it_j = j;
+							}
+							_ => {
+								if let Some(mut state) = egui::TextEdit::load_state(ui.ctx(), id) {
+									let cursor = state.cursor.char_range().map_or(0, |r| r.primary.index);
+									let cursor = keymap::edit(action, &mut entry.content, cursor);
+									let ccursor = egui::text::CCursor::new(cursor);
+									state.cursor.set_char_range(Some(egui::text::CCursorRange::one(ccursor)));
+									state.store(ui.ctx(), id);
+									entry.token_count = token_count(&entry.content);
+								}
+							}
+						}
+					}
+				}
+
+				ui.horizontal(|ui| {
+					ui.radio_value(&mut entry.role, User, "user");
+					ui.radio_value(&mut entry.role, System, "system");
+					ui.radio_value(&mut entry.role, Assistant, "assistant");
+
+					let mut layouter = |ui: &egui::Ui, string: &str, wrap_width: f32| {
+						let mut job = LayoutJob::default();
+						let completion = if editor_has_focus {
+							store.completion.split('\n').next().unwrap().to_owned()
+						} else {
+							String::new()
+						};
+						code::layout(
+							ui,
+							&mut job,
+							string,
+							TextFormat {
+								font_id: theme::editor_font(ui),
+								color: theme::role_color(ui, entry.role),
+								..Default::default()
+							},
+						);
+						job.append(
+							&completion,
+							0.0,
+							TextFormat {
+								font_id: theme::editor_font(ui),
+								color: ui.visuals().weak_text_color(),
+								..Default::default()
+							},
+						);
+						job.wrap.max_width = wrap_width;
+						ui.fonts(|f| f.layout_job(job))
+					};
+
+					let response = ui.add(
+						TextEdit::multiline(&mut entry.content)
+							.id(id)
+							.lock_focus(true)
+							// .font(FontId::new(20.0, FontFamily::Monospace))
+							.desired_width(f32::INFINITY)
+							.layouter(&mut layouter),
+					);
+					if response.gained_focus() {
+						response.scroll_to_me(None);
+					}
+					if response.changed() {
+						entry.token_count = token_count(&entry.content);
+						// eprintln!("{}", entry.content);
+						// let debounce_tx = self.debounce_tx.clone();
+						// let entry_content = entry.content.clone();
+						// tokio::spawn(async move {
+						// 	debounce_tx.unwrap().send(entry_content).await.unwrap();
+						// });
+					};
+					// if ui.button("remove").clicked() {
+					// 	WHEEL_WINDOWS.lock().unwrap().get_mut(i).unwrap().0.remove(j);
+					// }
+				});
+				ui.horizontal(|ui| {
+					ui.label(format!("{} tokens", entry.token_count));
+					if ui.small_button("📋").on_hover_text("copy").clicked() {
+						ui.output_mut(|o| o.copied_text = entry.content.clone());
+					}
+					if ui.small_button("🗑").on_hover_text("delete").clicked() {
+						message_action = Some((MessageAction::Delete, j));
+					}
+					if ui.add_enabled(j > 0, Button::new("⏶").small()).on_hover_text("move up").clicked() {
+						message_action = Some((MessageAction::MoveUp, j));
+					}
+					if ui
+						.add_enabled(j + 1 < message_ids.len(), Button::new("⏷").small())
+						.on_hover_text("move down")
+						.clicked()
+					{
+						message_action = Some((MessageAction::MoveDown, j));
+					}
+					if ui.small_button("⑂").on_hover_text("fork from here into a new window").clicked() {
+						fork = Some(j);
+					}
+					if ui.small_button("⧉").on_hover_text("duplicate").clicked() {
+						message_action = Some((MessageAction::Duplicate, j));
+					}
+					if ui
+						.selectable_label(entry.pinned, "📌")
+						.on_hover_text(
+							"pin: keep this message when sending again from above it, moving it up to just before that prompt",
+						)
+						.clicked()
+					{
+						message_action = Some((MessageAction::TogglePin, j));
+					}
+					if entry.role == Assistant
+						&& j > 0
+						&& ui.small_button("⟳").on_hover_text("regenerate this reply, keeping the messages after it").clicked()
+					{
+						regenerate = Some(j);
+					}
+					if entry.role == User {
+						if ui.small_button("🖼 attach image").clicked() {
+							entry.images.extend(ImageAttachment::pick_files());
+						}
+						if ui.small_button("📋 paste image").clicked() {
+							match ImageAttachment::from_clipboard() {
+								Ok(image) => entry.images.push(image),
+								Err(e) => eprintln!("paste image: {}", e),
+							}
+						}
+					}
+				});
+				code::buttons(ui, &entry.content);
+				if let Some(i) = images::thumbnails(ui, &entry.images) {
+					entry.images.remove(i);
+				}
+				total_tokens += entry.token_count;
+			}
+
+			if let Some((action, j)) = message_action {
+				self.checkpoint();
+				store.focus = self.apply(action, j);
+			}
+			if let Some(j) = fork {
+				store.open(self.fork(j));
+			}
+			if let Some(j) = regenerate {
+				self.checkpoint();
+				self.regenerate(ui.ctx(), store.sender(), j);
+			}
+
+			ui.label(
+				egui::RichText::new(format!(
+					"{} total tokens ({} cents) [{} to send]",
+					total_tokens,
+					((total_tokens * 15) as f64 / 1_000_000f64),
+					store.keymap.hint(ui.ctx(), Action::Send)
+				))
+				.color(ui.visuals().strong_text_color()),
+			);
+
+			if let Some(error) = &self.error {
+				ui.colored_label(ui.visuals().error_fg_color, error);
+			}
+
+			let extra_space = ui.clip_rect().height() - 300.0;
+			if extra_space > 5.0 {
+				let response = ui.allocate_space(egui::Vec2::new(ui.available_width(), extra_space));
+				if do_it {
+					ui.scroll_to_rect(response.1, Some(Align::TOP))
+				}
+			}
+
+			if do_it {
+				self.checkpoint();
+				self.send(ui.ctx(), store.sender(), do_it_j);
+				store.focus = self.messages.last().map(|m| m.id);
+			}
+		});
+	}
+
+	/// Sends the conversation up to the message at `index`, streaming the reply into a new
+	/// assistant message followed by an empty prompt. Pinned messages after `index` move up to just
+	/// before it.
+	fn send(&mut self, ctx: &Context, events: state::Sender, index: usize) {
+		self.truncate_after(index);
+		let ref mut messages = self.messages;
+		Prompt { rowid: None, time_ms: now_ms(), prompt: messages.last().unwrap().content.clone() }
+			.insert()
+			.unwrap();
+		let prompt = messages.clone();
+		let reply = ChatMessage::new(Assistant, String::new());
+		let reply_id = reply.id;
+		messages.push(reply);
+		messages.push(ChatMessage::new(User, String::new()));
+		self.stream_reply(ctx, events, prompt, reply_id);
+	}
+
+	/// Replaces the assistant message at `index` with a new reply to the messages before it, leaving
+	/// the messages after it as they are. Pinned messages after `index` are sent too, just before the
+	/// message being replied to, as `send` would place them.
+	fn regenerate(&mut self, ctx: &Context, events: state::Sender, index: usize) {
+		let mut prompt = self.messages[..index].to_vec();
+		let pinned = self.messages[index + 1..].iter().filter(|m| m.pinned).cloned();
+		let last = prompt.len().saturating_sub(1);
+		prompt.splice(last..last, pinned);
+		let reply = ChatMessage::new(Assistant, String::new());
+		let reply_id = reply.id;
+		self.messages[index] = reply;
+		self.stream_reply(ctx, events, prompt, reply_id);
+	}
+
+	/// Streams the reply to `prompt`, after the context, into the message `reply`.
+	fn stream_reply(
+		&mut self,
+		ctx: &Context,
+		events: state::Sender,
+		mut prompt: Vec<ChatMessage>,
+		reply: MessageId,
+	) {
+		context::reread(&mut self.context);
+		let context = context::snapshot(&mut self.context);
+		let (window_id, message_id) = (self.id, reply);
+		let (trigger, tripwire) = Tripwire::new();
+		self.stream = Some((message_id, trigger));
+		self.error = None;
+		let params = self.params.clone();
+		let ctx = ctx.clone();
+		tokio::spawn(async move {
+			prompt.splice(0..0, context.message().await);
+			let deltas = events.clone();
+			let repaint = ctx.clone();
+			let result = run_openai(params, tripwire, prompt, move |content| {
+				let content = content.clone();
+				deltas.send(Event::Delta { window: window_id, message: message_id, content }).ok();
+				repaint.request_repaint();
+			})
+			.await;
+			let error = result.err().map(|e| e.to_string());
+			events.send(Event::Finished { window: window_id, message: message_id, error }).ok();
+			ctx.request_repaint();
+		});
+	}
+}
 
 #[derive(Turbosql, Default)]
 struct Setting {
@@ -114,7 +534,15 @@ impl Resource {
 
 			Self { response, text: None, image: Some(image) }
 		} else {
-			Self { response, text: None, image: None }
+			let text = response.text().map(|text| {
+				if content_type.starts_with("text/html") {
+					html2text::from_read(text.as_bytes(), 100)
+				} else {
+					text.to_owned()
+				}
+			});
+
+			Self { response, text, image: None }
 		}
 	}
 }
@@ -131,13 +559,14 @@ pub struct App {
 	prompt_text: String,
 	completion_prompt: String,
 	saved_version: String,
+	fetch_open: bool,
+	#[serde(default)]
+	layout: layout::Layout,
 
 	#[serde(skip)]
 	debounce_tx: Option<Sender<String>>,
 	#[serde(skip)]
 	gpt_3_trigger: Option<Trigger>,
-	#[serde(skip)]
-	trigger: Option<Trigger>,
 	// #[serde(skip)]
 	// sessions: Vec<session::Session>,
 	#[serde(skip)]
@@ -145,27 +574,47 @@ pub struct App {
 	#[serde(skip)]
 	promise: Option<Promise<ehttp::Result<Resource>>>,
 	#[serde(skip)]
-	tokenizer: Option<tiktoken_rs::CoreBPE>,
+	secrets_ui: secrets::SecretsUi,
+	#[serde(skip)]
+	settings_window: settings::SettingsWindow,
+
+	#[serde(skip)]
+	dataset_window: dataset::DatasetWindow,
+	#[serde(skip)]
+	documents_window: library::DocumentsWindow,
+	#[serde(skip)]
+	search_window: library::SearchWindow,
+	#[serde(skip)]
+	relay_window: relay::RelayWindow,
+	#[serde(skip)]
+	store: state::Store,
+	#[serde(skip)]
+	theme: theme::Theme,
 }
 
 impl App {
-	pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
-		cc.egui_ctx.set_visuals(egui::style::Visuals::dark());
-
+	pub fn new(cc: &eframe::CreationContext<'_>, mut store: state::Store) -> Self {
 		egui_extras::install_image_loaders(&cc.egui_ctx);
 
 		let (debounce_tx, mut _debounce_rx) = mpsc::channel(10);
 
+		let latest_document =
+			select!(Option<Document> "ORDER BY timestamp_ms DESC LIMIT 1").unwrap().unwrap_or_default();
+
+		// Without a saved workspace, start with the latest document open.
+		if !cc.storage.is_some_and(|storage| store.restore(storage)) && latest_document.rowid.is_some() {
+			store.windows.push(WheelWindow {
+				messages: vec![ChatMessage::new(User, latest_document.content.clone())],
+				..WheelWindow::new()
+			});
+		}
+
 		let s = Self {
 			debounce_tx: Some(debounce_tx),
 			// sessions: session::Session::calculate_sessions(),
-			completion_prompt: COMPLETION_PROMPT.lock().unwrap().clone(),
-			tokenizer: Some(tiktoken_rs::o200k_base().unwrap()),
-			saved_version: select!(Option<Document> "ORDER BY timestamp_ms DESC LIMIT 1")
-				.unwrap()
-				.unwrap_or_default()
-				.content,
-			..Default::default()
+			store,
+			saved_version: latest_document.content,
+			..cc.storage.and_then(|storage| eframe::get_value(storage, eframe::APP_KEY)).unwrap_or_default()
 		};
 
 		let ctx_cloned = cc.egui_ctx.clone();
@@ -231,14 +680,59 @@ impl App {
 		// dbg!(session.duration_ms());
 		// dbg!(session.samples().len());
 
-		// Load previous app state (if any).
-		// Note that you must enable the `persistence` feature for this to work.
-		// if let Some(storage) = cc.storage {
-		//     return eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
-		// }
-
 		s
 	}
+
+	fn fetch(&mut self, ctx: &Context) {
+		let ctx = ctx.clone();
+		let (sender, promise) = Promise::new();
+		ehttp::fetch(ehttp::Request::get(&self.url), move |response| {
+			ctx.request_repaint();
+			sender.send(response.map(|response| Resource::from_response(&ctx, response)));
+		});
+		self.promise = Some(promise);
+	}
+
+	fn ui_fetch(&mut self, ctx: &Context, frame: &mut eframe::Frame) {
+		let mut open = self.fetch_open;
+		let mut trigger_fetch = false;
+
+		egui::Window::new("fetch").open(&mut open).default_size([640.0, 480.0]).show(ctx, |ui| {
+			trigger_fetch = ui_url(ui, frame, &mut self.url);
+
+			ui.separator();
+
+			if let Some(promise) = &self.promise {
+				match promise.ready() {
+					None => {
+						ui.spinner();
+					}
+					Some(Err(err)) => {
+						ui.colored_label(ui.visuals().error_fg_color, err);
+					}
+					Some(Ok(resource)) => {
+						if let Some(text) = &resource.text {
+							ui.horizontal_wrapped(|ui| {
+								ui.label("add as context to:");
+								for window in self.store.windows.iter_mut() {
+									if window.open && ui.button(format!("wheel {}", window.id)).clicked() {
+										window.context.push(ContextAttachment::from_web(&resource.response.url, text));
+									}
+								}
+							});
+							ui.separator();
+						}
+						ui_resource(ui, resource);
+					}
+				}
+			}
+		});
+
+		self.fetch_open = open;
+		if trigger_fetch {
+			self.fetch(ctx);
+		}
+	}
 }
 
 trait MyThings {
@@ -253,69 +747,108 @@ impl MyThings for Ui {
 				TextEdit::multiline(text)
 					.desired_width(f32::INFINITY)
 					// .desired_height(f32::INFINITY)
-					.font(FontId::new(30.0, FontFamily::Proportional)),
+					.font(theme::document_font(self)),
 			)
 			.changed()
 	}
 }
 
 impl eframe::App for App {
-	fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
-		let mut request_focus = None;
+	/// Window positions, scroll offsets and panel widths are kept in egui's memory, which eframe
+	/// saves alongside.
+	fn save(&mut self, storage: &mut dyn eframe::Storage) {
+		eframe::set_value(storage, eframe::APP_KEY, self);
+		self.store.save(storage);
+	}
+
+	fn update(&mut self, ctx: &Context, frame: &mut eframe::Frame) {
+		self.theme.apply(ctx, frame);
+		self.store.process_events(ctx);
+
 		let mut request_close = false;
-		ctx.input(|i| {
-			if i.key_pressed(Key::N) && i.modifiers.command {
-				let mut wheel_windows = WHEEL_WINDOWS.lock().unwrap();
-				let len = wheel_windows.len();
-				wheel_windows.push(Default::default());
-				request_focus = Some(len * 1000);
-			}
-			if i.key_pressed(Key::W) && i.modifiers.command {
-				request_close = true;
-			}
-			if i.key_pressed(Key::S) && i.modifiers.command {
-				let wheel_windows = WHEEL_WINDOWS.lock().unwrap();
-				if let Some(window) = wheel_windows.get(0)
-					&& let Some(message) = window.messages.get(0)
-				{
-					Document {
-						rowid: None,
-						title: "primary".into(),
-						content: message.content.clone(),
-						timestamp_ms: now_ms(),
+		let mut cycle_windows = 0;
+		let mut cycle_panes = 0;
+		let dropped_files = ctx.input(|i| i.raw.dropped_files.clone());
+
+		self.store.keymap.refresh();
+		self.store.keymap.record(ctx);
+		// Consumed so the focused editor doesn't also see them.
+		let actions = ctx.input_mut(|i| self.store.keymap.pressed(i, keymap::Scope::Global));
+		for action in actions {
+			match action {
+				Action::NewWindow => self.store.open(WheelWindow::new()),
+				Action::CloseWindow => request_close = true,
+				Action::ReopenWindow => self.store.reopen(),
+				Action::PreviousWindow => cycle_windows = -1,
+				Action::NextWindow => cycle_windows = 1,
+				Action::PreviousPane => cycle_panes = -1,
+				Action::NextPane => cycle_panes = 1,
+				Action::CheatSheet => self.store.keymap.open ^= true,
+				Action::Save => {
+					if let Some(window) = self.store.windows.first()
+						&& let Some(message) = window.messages.first()
+					{
+						Document {
+							rowid: None,
+							title: "primary".into(),
+							content: message.content.clone(),
+							timestamp_ms: now_ms(),
+						}
+						.insert()
+						.unwrap();
+						self.saved_version = message.content.clone();
 					}
-					.insert()
-					.unwrap();
-					self.saved_version = message.content.clone();
 				}
+				_ => {}
 			}
-		});
+		}
 
 		SidePanel::left("left_panel").show(ctx, |ui| {
 			ui.label(option_env!("BUILD_ID").unwrap_or("DEV"));
 
-			let wheel_windows = WHEEL_WINDOWS.lock().unwrap();
-			if let Some(window) = wheel_windows.get(0)
-				&& let Some(message) = window.messages.get(0)
+			if let Some(window) = self.store.windows.first()
+				&& let Some(message) = window.messages.first()
 			{
 				if self.saved_version == message.content {
 					ui.label("SAVED");
 				}
 			}
 
-			let mut setting2 = Setting::get("openai_api_key");
-			ui.label("openai api key:");
-			ui
-				.add(TextEdit::singleline(&mut setting2.value).desired_width(f32::INFINITY))
-				.changed()
-				.then(|| setting2.save());
+			ui.toggle_value(&mut self.settings_window.open, "settings");
+			ui.toggle_value(&mut self.fetch_open, "fetch url");
+			ui.toggle_value(&mut self.dataset_window.open, "dataset");
+			ui.toggle_value(&mut self.documents_window.open, "documents");
+			ui.toggle_value(&mut self.search_window.open, "search");
+			ui.toggle_value(&mut self.relay_window.open, "relay");
+			ui.toggle_value(&mut self.store.keymap.open, "keyboard shortcuts");
+
+			if ui
+				.add_enabled(!self.store.closed.is_empty(), Button::new("↺ reopen closed window"))
+				.on_hover_text(self.store.keymap.hint(ctx, Action::ReopenWindow))
+				.clicked()
+			{
+				self.store.reopen();
+			}
+
+			if ui.button("import conversation…").clicked()
+				&& let Some(path) =
+					rfd::FileDialog::new().add_filter("conversation", &["md", "json", "jsonl"]).pick_file()
+			{
+				match transcript::import_file(&path) {
+					Ok(conversations) => {
+						for mut messages in conversations {
+							if !messages.last().is_some_and(|m| m.role == User) {
+								messages.push(ChatMessage::new(User, String::new()));
+							}
+							self.store.open(WheelWindow { messages, ..WheelWindow::new() });
+						}
+					}
+					Err(e) => eprintln!("{}: {}", path.display(), e),
+				}
+			}
 
-			let mut setting3 = Setting::get_with_default("openai_model", "gpt-4o-mini");
-			ui.label("openai model:");
-			ui
-				.add(TextEdit::singleline(&mut setting3.value).desired_width(f32::INFINITY))
-				.changed()
-				.then(|| setting3.save());
+			ui.separator();
+			self_update::ui(ui, &mut self.store);
 
 			ScrollArea::vertical().auto_shrink([false, false]).show(ui, |_ui| {
 				// let size = [ui.available_width(), ui.spacing().interact_size.y.max(20.0)];
@@ -329,171 +862,83 @@ impl eframe::App for App {
 			});
 		});
 
-		for (window_num, window) in WHEEL_WINDOWS.lock().unwrap().iter_mut().enumerate() {
-			if window.request_close {
-				window.open = false;
-			}
-			egui::Window::new(format!("wheel {}", window_num)).open(&mut window.open).show(ctx, |ui| {
-				if request_close && Some(ui.layer_id()) == ui.ctx().top_layer_id() {
-					window.request_close = true;
-				}
+		if cycle_windows != 0 {
+			self.store.navigate = self.store.cycle_windows(cycle_windows);
+		}
+		if let Some((window, message)) = self.store.navigate.take() {
+			ctx.move_to_top(LayerId::new(Order::Middle, window.egui_id()));
+			self.layout.reveal(layout::Pane::Wheel(window));
+			self.store.focus = Some(message);
+		}
 
-				ScrollArea::vertical().show(ui, |ui| {
-					if ui.button("copy all to clipboard").clicked() {
-						let mut text = "\n".to_string();
+		// Taken so windows can be drawn while the rest of the store is borrowed. Windows opened
+		// while drawing go after them.
+		let mut windows = std::mem::take(&mut self.store.windows);
 
-						for entry in window.messages.iter() {
-							text.push_str(&format!("[{}]: {}\n", entry.role, entry.content));
-						}
+		if self.layout.tiled() {
+			if cycle_panes != 0 {
+				self.layout.cycle(cycle_panes, &windows, &mut self.store);
+			}
+			if request_close
+				&& let Some(window) = windows.iter_mut().find(|w| Some(w.id) == self.store.active)
+			{
+				window.open = false;
+			}
 
-						ui.output_mut(|o| o.copied_text = text);
+			self.layout.ui(
+				ctx,
+				layout::Panes {
+					windows: &mut windows,
+					store: &mut self.store,
+					dropped_files: &dropped_files,
+					documents: &mut self.documents_window,
+					search: &mut self.search_window,
+					settings: &mut self.settings_window,
+					secrets_ui: &mut self.secrets_ui,
+					dataset: &mut self.dataset_window,
+				},
+			);
+		} else {
+			for window in windows.iter_mut() {
+				if window.request_close {
+					window.open = false;
+				}
+				let egui_window = egui::Window::new(format!("wheel {}", window.id)).id(window.id.egui_id());
+				egui_window.open(&mut window.open).show(ctx, |ui| {
+					if request_close && Some(ui.layer_id()) == ui.ctx().top_layer_id() {
+						window.request_close = true;
 					}
-					let mut do_it = false;
-					let mut do_it_j = 9999;
-					let mut total_tokens = 0;
-					for (j, entry) in window.messages.iter_mut().enumerate() {
-						let id = Id::new(window_num * 1000 + j);
-						let editor_has_focus = ui.ctx().memory(|m| m.has_focus(id));
-
-						if editor_has_focus && ui.input_mut(|i| i.consume_key(Modifiers::default(), Key::Tab)) {
-							entry.content.push_str(COMPLETION.lock().unwrap().as_str().split('\n').next().unwrap());
-							COMPLETION.lock().unwrap().clear();
-							if let Some(mut state) = egui::TextEdit::load_state(ctx, id) {
-								let ccursor = egui::text::CCursor::new(entry.content.chars().count());
-								state.cursor.set_char_range(Some(egui::text::CCursorRange::one(ccursor)));
-								state.store(ctx, id);
-								// ui.ctx().memory().request_focus(text_edit_id); // give focus back to the `TextEdit`.
-							}
-						}
-						if editor_has_focus
-							&& ui
-								.input_mut(|i| i.consume_key(Modifiers { command: true, ..Default::default() }, Key::Enter))
-						{
-							COMPLETION.lock().unwrap().clear();
-							do_it = true;
-							do_it_j = j;
-						}
 
-						ui.horizontal(|ui| {
-							ui.radio_value(&mut entry.role, User, "user");
-							ui.radio_value(&mut entry.role, System, "system");
-							ui.radio_value(&mut entry.role, Assistant, "assistant");
-
-							let mut layouter = |ui: &egui::Ui, string: &str, wrap_width: f32| {
-								let mut job = LayoutJob::default();
-								let completion = if editor_has_focus {
-									let string = COMPLETION.lock().unwrap();
-									string.split('\n').next().unwrap().to_owned()
-								} else {
-									String::new()
-								};
-								job.append(
-									string,
-									0.0,
-									TextFormat {
-										font_id: FontId::new(20.0, FontFamily::Monospace),
-										color: if entry.role == Assistant {
-											Color32::from_rgb(0xCD, 0xD8, 0xFF)
-										} else {
-											Color32::from_rgb(230, 230, 230)
-										},
-										..Default::default()
-									},
-								);
-								job.append(
-									&completion,
-									0.0,
-									TextFormat {
-										font_id: FontId::new(20.0, FontFamily::Monospace),
-										color: Color32::DARK_GRAY,
-										..Default::default()
-									},
-								);
-								job.wrap.max_width = wrap_width;
-								ui.fonts(|f| f.layout_job(job))
-							};
-
-							if ui
-								.add(
-									TextEdit::multiline(&mut entry.content)
-										.id(id)
-										.lock_focus(true)
-										// .font(FontId::new(20.0, FontFamily::Monospace))
-										.desired_width(f32::INFINITY)
-										.layouter(&mut layouter),
-								)
-								.changed()
-							{
-								entry.token_count =
-									self.tokenizer.as_ref().unwrap().encode_with_special_tokens(&entry.content).len();
-								// eprintln!("{}", entry.content);
-								// let debounce_tx = self.debounce_tx.clone();
-								// let entry_content = entry.content.clone();
-								// tokio::spawn(async move {
-								// 	debounce_tx.unwrap().send(entry_content).await.unwrap();
-								// });
-							};
-							// if ui.button("remove").clicked() {
-							// 	WHEEL_WINDOWS.lock().unwrap().get_mut(i).unwrap().0.remove(j);
-							// }
-						});
-						ui.label(format!("{} tokens", entry.token_count));
-						total_tokens += entry.token_count;
-					}
+					window.ui(ui, &mut self.store, &dropped_files);
+				});
+			}
 
-					ui.label(
-						egui::RichText::new(format!(
-							"{} total tokens ({} cents) [command-enter to send]",
-							total_tokens,
-							((total_tokens * 15) as f64 / 1_000_000f64)
-						))
-						.color(Color32::WHITE),
-					);
+			self.settings_window.show(ctx, &mut self.secrets_ui);
+			self.dataset_window.show(ctx);
+			self.documents_window.show(ctx, &mut self.store);
+			self.search_window.show(ctx, &mut self.store);
 
-					let extra_space = ui.clip_rect().height() - 300.0;
-					if extra_space > 5.0 {
-						let response = ui.allocate_space(egui::Vec2::new(ui.available_width(), extra_space));
-						if do_it {
-							ui.scroll_to_rect(response.1, Some(Align::TOP))
-						}
-					}
+			CentralPanel::default().show(ctx, |_ui| {});
+		}
 
-					if let Some(id) = request_focus {
-						ui.ctx().memory_mut(|m| m.request_focus(Id::new(id)))
-					};
+		windows.append(&mut self.store.windows);
+		self.store.windows = windows;
+		self.store.remove_closed();
 
-					if do_it {
-						let ref mut messages = window.messages;
-						messages.truncate(do_it_j + 1);
-						Prompt { rowid: None, time_ms: now_ms(), prompt: messages.last().unwrap().content.clone() }
-							.insert()
-							.unwrap();
-						let orig_messages = messages.clone();
-						messages.push(ChatMessage { role: Assistant, content: String::new(), token_count: 0 });
-						messages.push(ChatMessage { role: User, content: String::new(), token_count: 0 });
-						ui.ctx().memory_mut(|m| m.request_focus(Id::new((window_num * 1000) + messages.len() - 1)));
-						let id = messages.len() - 2;
-						let ctx_cloned = ctx.clone();
-						let (trigger, tripwire) = Tripwire::new();
-						self.trigger = Some(trigger);
-						tokio::spawn(async move {
-							run_openai(Setting::get("openai_model").value, tripwire, orig_messages, move |content| {
-								let mut wheel_windows = WHEEL_WINDOWS.lock().unwrap();
-								let entry = wheel_windows.get_mut(window_num).unwrap().messages.get_mut(id).unwrap();
-								entry.content.push_str(content);
-								entry.token_count =
-									TOKENIZER.lock().unwrap().encode_with_special_tokens(&entry.content).len();
-								ctx_cloned.request_repaint();
-							})
-							.await
-							.unwrap();
-						});
-					}
-				});
-			});
+		if let Some(message) = self.store.focus.take() {
+			ctx.memory_mut(|m| m.request_focus(message.editor_id()));
 		}
+		if let Some(message) = self.store.restore_focus.take()
+			&& ctx.memory(|m| m.focused().is_none())
+		{
+			ctx.memory_mut(|m| m.request_focus(message.editor_id()));
+		}
+
+		self.ui_fetch(ctx, frame);
+		self.store.keymap.show(ctx);
+		self.relay_window.show(ctx, &mut self.store);
 
-		CentralPanel::default().show(ctx, |_ui| {});
+		compare::show(ctx, &mut self.store);
 	}
 }
 
@@ -569,21 +1014,49 @@ fn selectable_text(ui: &mut Ui, mut text: &str) {
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
 	env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
 
+	let cli = cli::parse();
+
+	if let Some(db) = &cli.db {
+		turbosql::set_db_path(db)?;
+	}
+
 	eprintln!("database at {:?}", turbosql::db_path());
 
-	self_update::self_update().await.ok();
+	if let Some(command) = cli.command {
+		return cli::run(command).await;
+	}
 
-	if let Some(document) = select!(Option<Document> "ORDER BY timestamp_ms DESC LIMIT 1")? {
-		WHEEL_WINDOWS.lock().unwrap().push(WheelWindow {
-			messages: vec![ChatMessage {
-				role: User,
-				content: document.content.clone(),
-				token_count: TOKENIZER.lock().unwrap().encode_with_special_tokens(&document.content).len(),
-			}],
-			..Default::default()
-		});
+	match self_update::apply_staged() {
+		Ok(true) => {
+			std::process::Command::new(std::env::current_exe()?)
+				.args(std::env::args_os().skip(1))
+				.spawn()?;
+			return Ok(());
+		}
+		Ok(false) => {}
+		Err(e) => eprintln!("applying staged update failed: {}", e),
 	}
 
+	let store = state::Store::default();
+	let events = store.sender();
+
+	self_update::spawn(events.clone());
+
+	server::restart(&events);
+
+	tokio::spawn(async move {
+		let mut changes = settings::subscribe();
+		loop {
+			match changes.recv().await {
+				Ok("update_policy" | "update_channel") => self_update::spawn(events.clone()),
+				Ok("secret_store") => secrets::store_changed(),
+				Ok("api_server" | "api_port") => server::restart(&events),
+				Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+				Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+			}
+		}
+	});
+
 	// Ok(())
 	// let rt = tokio::runtime::Runtime::new().expect("Unable to create Runtime");
 
@@ -600,14 +1073,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 				.with_min_inner_size([300.0, 220.0]),
 			..Default::default()
 		},
-		Box::new(|cc| Ok(Box::new(App::new(cc)))),
+		Box::new(move |cc| Ok(Box::new(App::new(cc, store)))),
 	)?;
 
 	Ok(())
 }
 
 pub(crate) async fn run_openai(
-	model: impl AsRef<str>,
+	params: GenerationParams,
 	tripwire: Tripwire,
 	messages: Vec<ChatMessage>,
 	callback: impl Fn(&String) + Send + 'static,
@@ -616,9 +1089,14 @@ pub(crate) async fn run_openai(
 	use futures::StreamExt;
 
 	let client = Client::with_config(
-		async_openai::config::OpenAIConfig::new().with_api_key(Setting::get("openai_api_key").value),
+		async_openai::config::OpenAIConfig::new().with_api_key(secrets::openai_api_key()),
 	);
 
+	// A panic below would end the task before it reports `Finished`.
+	if let Some(m) = messages.iter().find(|m| !matches!(m.role, System | User | Assistant)) {
+		Err(format!("unsupported role {}", m.role))?;
+	}
+
 	let messages = messages
 		.into_iter()
 		.map(|m| match m.role {
@@ -627,6 +1105,13 @@ pub(crate) async fn run_openai(
 				.build()
 				.unwrap()
 				.into(),
+			User if !m.images.is_empty() => {
+				async_openai::types::ChatCompletionRequestUserMessageArgs::default()
+					.content(images::content_parts(m.content, &m.images))
+					.build()
+					.unwrap()
+					.into()
+			}
 			User => async_openai::types::ChatCompletionRequestUserMessageArgs::default()
 				.content(m.content)
 				.build()
@@ -637,7 +1122,7 @@ pub(crate) async fn run_openai(
 				.build()
 				.unwrap()
 				.into(),
-			_ => panic!("invalid role"),
+			_ => unreachable!("roles are checked above"),
 		})
 		.collect::<Vec<ChatCompletionRequestMessage>>();
 
@@ -654,9 +1139,9 @@ pub(crate) async fn run_openai(
 
 	// dbg!(&messages);
 
-	let request = CreateChatCompletionRequestArgs::default()
-		.model(model.as_ref().to_owned())
-		.max_tokens(16384u16)
+	let mut request = CreateChatCompletionRequestArgs::default();
+	params.apply(&mut request);
+	let request = request
 		.messages(messages)
 		// .tools(vec![ChatCompletionToolArgs::default()
 		// 	.r#type(ChatCompletionToolType::Function)
@@ -692,9 +1177,7 @@ pub(crate) async fn run_openai(
 					}
 				});
 			}
-			Err(err) => {
-				panic!("error: {err}");
-			}
+			Err(err) => Err(err)?,
 		}
 	}
 
@@ -0,0 +1,121 @@
+use super::settings;
+use async_openai::types::{
+	ChatCompletionResponseFormat, ChatCompletionResponseFormatType, CreateChatCompletionRequestArgs,
+	Stop,
+};
+use egui::*;
+use serde::{Deserialize, Serialize};
+
+/// The most stop sequences the API accepts.
+const MAX_STOP: usize = 4;
+
+/// Sampling parameters for one wheel window, saved with the workspace.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct GenerationParams {
+	pub(crate) model: String,
+	pub(crate) temperature: f32,
+	pub(crate) top_p: f32,
+	pub(crate) max_tokens: u16,
+	/// One stop sequence per line; blank lines are ignored. At most [`MAX_STOP`] are non-blank.
+	pub(crate) stop: Vec<String>,
+	pub(crate) seed: Option<i64>,
+	pub(crate) json_mode: bool,
+}
+
+impl Default for GenerationParams {
+	fn default() -> Self {
+		Self {
+			model: "gpt-4o-mini".into(),
+			temperature: 1.0,
+			top_p: 1.0,
+			max_tokens: 16384,
+			stop: Vec::new(),
+			seed: None,
+			json_mode: false,
+		}
+	}
+}
+
+impl GenerationParams {
+	/// Defaults for a fresh window, with the model taken from the `openai_model` setting.
+	pub(crate) fn from_settings() -> Self {
+		Self { model: settings::get("openai_model"), ..Default::default() }
+	}
+
+	fn stop_sequences(stop: &[String]) -> Vec<String> {
+		stop.iter().filter(|s| !s.is_empty()).cloned().collect()
+	}
+
+	pub(crate) fn apply(&self, request: &mut CreateChatCompletionRequestArgs) {
+		request
+			.model(self.model.clone())
+			.temperature(self.temperature)
+			.top_p(self.top_p)
+			.max_tokens(self.max_tokens);
+
+		let stop = Self::stop_sequences(&self.stop);
+		if !stop.is_empty() {
+			request.stop(Stop::StringArray(stop));
+		}
+		if let Some(seed) = self.seed {
+			request.seed(seed);
+		}
+		if self.json_mode {
+			request.response_format(ChatCompletionResponseFormat {
+				r#type: ChatCompletionResponseFormatType::JsonObject,
+			});
+		}
+	}
+
+	pub(crate) fn ui(&mut self, ui: &mut Ui) {
+		Grid::new(ui.next_auto_id()).num_columns(2).show(ui, |ui| {
+			ui.label("model");
+			ui.add(TextEdit::singleline(&mut self.model).desired_width(f32::INFINITY));
+			ui.end_row();
+
+			ui.label("temperature");
+			ui.add(Slider::new(&mut self.temperature, 0.0..=2.0));
+			ui.end_row();
+
+			ui.label("top_p");
+			ui.add(Slider::new(&mut self.top_p, 0.0..=1.0));
+			ui.end_row();
+
+			ui.label("max tokens");
+			ui.add(DragValue::new(&mut self.max_tokens).range(1..=u16::MAX));
+			ui.end_row();
+
+			ui.label("stop");
+			ui.vertical(|ui| {
+				let mut stop = self.stop.join("\n");
+				if ui.add(TextEdit::multiline(&mut stop).desired_rows(1).hint_text("one per line")).changed() {
+					let stop = stop.split('\n').map(String::from).collect::<Vec<_>>();
+					if Self::stop_sequences(&stop).len() <= MAX_STOP {
+						self.stop = stop;
+					}
+				}
+				if Self::stop_sequences(&self.stop).len() >= MAX_STOP {
+					ui.label(RichText::new(format!("at most {} stop sequences", MAX_STOP)).weak());
+				}
+			});
+			ui.end_row();
+
+			ui.label("seed");
+			ui.horizontal(|ui| {
+				let mut fixed = self.seed.is_some();
+				if ui.checkbox(&mut fixed, "").changed() {
+					self.seed = fixed.then_some(0);
+				}
+				if let Some(seed) = &mut self.seed {
+					ui.add(DragValue::new(seed));
+				}
+			});
+			ui.end_row();
+
+			ui.label("json mode");
+			ui.checkbox(&mut self.json_mode, "");
+			ui.end_row();
+		});
+	}
+}
@@ -0,0 +1,172 @@
+use super::*;
+
+/// Sends one window's finished replies to another window as prompts, so windows can take turns,
+/// e.g. an author and a critic. Link each way for a loop.
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct Link {
+	pub(crate) from: WindowId,
+	pub(crate) to: WindowId,
+	/// Replies relayed since the link was started.
+	pub(crate) turns: usize,
+	pub(crate) max_turns: usize,
+	/// Stops relaying at a reply containing this, unless it's empty.
+	pub(crate) stop_phrase: String,
+	pub(crate) running: bool,
+	/// Why the link last stopped.
+	#[serde(skip)]
+	status: Option<String>,
+}
+
+impl Link {
+	fn new(from: WindowId, to: WindowId) -> Self {
+		Self {
+			from,
+			to,
+			turns: 0,
+			max_turns: 10,
+			stop_phrase: String::new(),
+			running: false,
+			status: None,
+		}
+	}
+
+	fn stop(&mut self, reason: &str) {
+		self.running = false;
+		self.status = Some(reason.to_owned());
+	}
+}
+
+/// Relays the reply `message` that `from` just finished along each running link from it.
+pub(crate) fn relay(store: &mut state::Store, ctx: &Context, from: WindowId, message: MessageId) {
+	let Some(reply) = store
+		.windows
+		.iter()
+		.find(|w| w.id == from)
+		.and_then(|w| w.messages.iter().find(|m| m.id == message))
+		.map(|m| m.content.clone())
+	else {
+		return;
+	};
+	let events = store.sender();
+
+	for link in store.links.iter_mut().filter(|l| l.running && l.from == from) {
+		if link.turns >= link.max_turns {
+			link.stop("turn limit reached");
+			continue;
+		}
+		if !link.stop_phrase.is_empty() && reply.contains(&link.stop_phrase) {
+			link.stop("stop phrase found");
+			continue;
+		}
+		if reply.trim().is_empty() {
+			link.stop("empty reply");
+			continue;
+		}
+		if link.to == from {
+			link.stop("a window can't relay to itself");
+			continue;
+		}
+		let Some(window) = store.windows.iter_mut().find(|w| w.id == link.to) else {
+			link.stop("window closed");
+			continue;
+		};
+		// Sending now would cancel the reply it's streaming.
+		if window.stream.is_some() {
+			link.status = Some(format!("skipped a reply while wheel {} was busy", link.to));
+			continue;
+		}
+
+		link.turns += 1;
+		window.checkpoint();
+		let index = window.append(ChatMessage::new(User, reply.clone()));
+		window.send(ctx, events.clone(), index);
+	}
+}
+
+/// Lists the links between wheel windows, with controls to add, start, stop and remove them.
+#[derive(Default)]
+pub(crate) struct RelayWindow {
+	pub(crate) open: bool,
+}
+
+impl RelayWindow {
+	pub(crate) fn show(&mut self, ctx: &Context, store: &mut state::Store) {
+		let mut open = self.open;
+		egui::Window::new("relay").open(&mut open).show(ctx, |ui| self.ui(ui, store));
+		self.open = open;
+	}
+
+	fn ui(&mut self, ui: &mut Ui, store: &mut state::Store) {
+		let windows = store.windows.iter().map(|w| w.id).collect::<Vec<_>>();
+		let mut remove = None;
+
+		ui.label(
+			RichText::new("Each finished reply in the first window is sent as a prompt in the second.")
+				.weak(),
+		);
+
+		for (i, link) in store.links.iter_mut().enumerate() {
+			ui.horizontal(|ui| {
+				if ui.small_button("✖").on_hover_text("remove link").clicked() {
+					remove = Some(i);
+				}
+				ui.add_enabled_ui(!link.running, |ui| {
+					window_picker(ui, ("relay_from", i), &windows, link.to, &mut link.from);
+					ui.label("→");
+					window_picker(ui, ("relay_to", i), &windows, link.from, &mut link.to);
+					ui.add(DragValue::new(&mut link.max_turns).range(1..=100).prefix("max turns: "));
+					ui.add(
+						TextEdit::singleline(&mut link.stop_phrase).hint_text("stop phrase").desired_width(100.0),
+					);
+				});
+
+				if link.running {
+					if ui.button("⏹ stop").clicked() {
+						link.stop("stopped");
+					}
+				} else if ui
+					.add_enabled(link.from != link.to, Button::new("▶ start"))
+					.on_hover_text("relay the next replies")
+					.clicked()
+				{
+					link.turns = 0;
+					link.running = true;
+					link.status = None;
+				}
+				ui.label(format!("{}/{} turns", link.turns, link.max_turns));
+				if let Some(status) = &link.status {
+					ui.label(RichText::new(status).weak());
+				}
+			});
+		}
+		if let Some(i) = remove {
+			store.links.remove(i);
+		}
+
+		ui.horizontal(|ui| {
+			if ui.add_enabled(windows.len() >= 2, Button::new("+ add link")).clicked() {
+				store.links.push(Link::new(windows[0], windows[1]));
+			}
+			if ui.add_enabled(store.links.iter().any(|l| l.running), Button::new("⏹ stop all")).clicked() {
+				for link in store.links.iter_mut().filter(|l| l.running) {
+					link.stop("stopped");
+				}
+			}
+		});
+	}
+}
+
+/// Picks one of `windows` other than `other`, the window at the link's other end.
+fn window_picker(
+	ui: &mut Ui,
+	id: impl std::hash::Hash,
+	windows: &[WindowId],
+	other: WindowId,
+	selected: &mut WindowId,
+) {
+	ComboBox::from_id_source(id).selected_text(format!("wheel {}", selected)).show_ui(ui, |ui| {
+		for &window in windows.iter().filter(|&&w| w != other) {
+			ui.selectable_value(selected, window, format!("wheel {}", window));
+		}
+	});
+}
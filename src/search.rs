@@ -0,0 +1,62 @@
+use super::*;
+
+/// A past prompt or saved document containing a query.
+pub(crate) enum SearchResult {
+	Prompt(Prompt),
+	Document(Document),
+}
+
+impl SearchResult {
+	pub(crate) fn kind(&self) -> &'static str {
+		match self {
+			SearchResult::Prompt(_) => "prompt",
+			SearchResult::Document(_) => "document",
+		}
+	}
+
+	pub(crate) fn time_ms(&self) -> i64 {
+		match self {
+			SearchResult::Prompt(prompt) => prompt.time_ms,
+			SearchResult::Document(document) => document.timestamp_ms,
+		}
+	}
+
+	pub(crate) fn content(&self) -> &str {
+		match self {
+			SearchResult::Prompt(prompt) => &prompt.prompt,
+			SearchResult::Document(document) => &document.content,
+		}
+	}
+}
+
+/// Past prompts containing `query`, newest first.
+pub(crate) fn prompts(query: &str, limit: i64) -> Result<Vec<Prompt>, Box<dyn std::error::Error>> {
+	let pattern = format!("%{}%", query);
+	Ok(select!(Vec<Prompt> "WHERE prompt LIKE " pattern " ORDER BY time_ms DESC LIMIT " limit)?)
+}
+
+/// Saved documents containing `query`, newest first.
+pub(crate) fn documents(
+	query: &str,
+	limit: i64,
+) -> Result<Vec<Document>, Box<dyn std::error::Error>> {
+	let pattern = format!("%{}%", query);
+	Ok(
+		select!(Vec<Document> "WHERE content LIKE " pattern " ORDER BY timestamp_ms DESC LIMIT " limit)?,
+	)
+}
+
+/// Up to `limit` prompts, then up to `limit` documents, containing `query`.
+pub(crate) fn search(
+	query: &str,
+	limit: i64,
+) -> Result<Vec<SearchResult>, Box<dyn std::error::Error>> {
+	let prompts = prompts(query, limit)?.into_iter().map(SearchResult::Prompt);
+	let documents = documents(query, limit)?.into_iter().map(SearchResult::Document);
+	Ok(prompts.chain(documents).collect())
+}
+
+/// The first line of `text` that isn't blank.
+pub(crate) fn first_line(text: &str) -> &str {
+	text.lines().find(|l| !l.trim().is_empty()).unwrap_or_default()
+}
@@ -0,0 +1,416 @@
+use super::{settings, Setting};
+use base64::prelude::*;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use egui::*;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once};
+
+pub(crate) const OPENAI_API_KEY: &str = "openai_api_key";
+
+const SERVICE: &str = "brace";
+
+/// Unlocks the encrypted file without the settings panel, e.g. for the CLI.
+const PASSPHRASE_VAR: &str = "BRACE_PASSPHRASE";
+
+/// The unlocked encrypted file, held only for the life of the process.
+static UNLOCKED: Lazy<Mutex<Option<Unlocked>>> = Lazy::new(Default::default);
+
+static ENV_UNLOCK: Once = Once::new();
+
+/// Whether plaintext keys have been moved into the active store.
+static MIGRATED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the active store holds an API key; `None` until looked up, since keyring lookups are slow.
+static KEY_SAVED: Lazy<Mutex<Option<bool>>> = Lazy::new(Default::default);
+
+static KEYRING_AVAILABLE: Lazy<bool> = Lazy::new(|| {
+	keyring::Entry::new(SERVICE, "probe")
+		.and_then(|entry| match entry.get_password() {
+			Ok(_) | Err(keyring::Error::NoEntry) => Ok(()),
+			Err(e) => Err(e),
+		})
+		.is_ok()
+});
+
+pub(crate) trait SecretStore {
+	fn describe(&self) -> &'static str;
+	fn get(&self, key: &str) -> Result<Option<String>, Box<dyn std::error::Error>>;
+	/// Stores `value`, or removes the secret if `value` is empty.
+	fn set(&self, key: &str, value: &str) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+/// The OS credential store: Keychain, Credential Manager, or the Secret Service.
+struct Keyring;
+
+impl SecretStore for Keyring {
+	fn describe(&self) -> &'static str {
+		"OS keyring"
+	}
+
+	fn get(&self, key: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+		match keyring::Entry::new(SERVICE, key)?.get_password() {
+			Ok(value) => Ok(Some(value)),
+			Err(keyring::Error::NoEntry) => Ok(None),
+			Err(e) => Err(e.into()),
+		}
+	}
+
+	fn set(&self, key: &str, value: &str) -> Result<(), Box<dyn std::error::Error>> {
+		let entry = keyring::Entry::new(SERVICE, key)?;
+		match value {
+			"" => match entry.delete_credential() {
+				Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+				Err(e) => Err(e.into()),
+			},
+			value => Ok(entry.set_password(value)?),
+		}
+	}
+}
+
+/// A ChaCha20-Poly1305 encrypted file next to the database, keyed from a passphrase with Argon2.
+/// Reads and writes go through the secrets decrypted at unlock.
+struct EncryptedFile;
+
+struct Unlocked {
+	salt: [u8; 16],
+	key: Key,
+	secrets: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct EncryptedContents {
+	salt: String,
+	nonce: String,
+	ciphertext: String,
+}
+
+fn secrets_path() -> PathBuf {
+	turbosql::db_path().with_file_name("secrets.enc")
+}
+
+/// Whether an encrypted file exists; if not, unlocking creates one with the given passphrase.
+pub(crate) fn file_exists() -> bool {
+	secrets_path().exists()
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key, Box<dyn std::error::Error>> {
+	let mut key = Key::default();
+	argon2::Argon2::default()
+		.hash_password_into(passphrase.as_bytes(), salt, &mut key)
+		.map_err(|e| e.to_string())?;
+	Ok(key)
+}
+
+/// Decrypts the encrypted file, or creates an empty one, and keeps it unlocked.
+pub(crate) fn unlock(passphrase: &str) -> Result<(), Box<dyn std::error::Error>> {
+	let unlocked = match std::fs::read_to_string(secrets_path()) {
+		Ok(contents) => {
+			let contents = serde_json::from_str::<EncryptedContents>(&contents)?;
+			let salt: [u8; 16] =
+				BASE64_STANDARD.decode(contents.salt)?.try_into().map_err(|_| "corrupted secrets file")?;
+			let key = derive_key(passphrase, &salt)?;
+			let plaintext = ChaCha20Poly1305::new(&key)
+				.decrypt(
+					Nonce::from_slice(&BASE64_STANDARD.decode(contents.nonce)?),
+					BASE64_STANDARD.decode(contents.ciphertext)?.as_ref(),
+				)
+				.map_err(|_| "wrong passphrase or corrupted secrets file")?;
+			Unlocked { salt, key, secrets: serde_json::from_slice(&plaintext)? }
+		}
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+			let mut salt = [0u8; 16];
+			OsRng.fill_bytes(&mut salt);
+			let unlocked = Unlocked { salt, key: derive_key(passphrase, &salt)?, secrets: BTreeMap::new() };
+			unlocked.save()?;
+			unlocked
+		}
+		Err(e) => return Err(e.into()),
+	};
+	*UNLOCKED.lock().unwrap() = Some(unlocked);
+	Ok(())
+}
+
+impl Unlocked {
+	fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+		let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+		let ciphertext = ChaCha20Poly1305::new(&self.key)
+			.encrypt(&nonce, serde_json::to_vec(&self.secrets)?.as_ref())
+			.map_err(|e| e.to_string())?;
+		let contents = EncryptedContents {
+			salt: BASE64_STANDARD.encode(self.salt),
+			nonce: BASE64_STANDARD.encode(nonce),
+			ciphertext: BASE64_STANDARD.encode(ciphertext),
+		};
+		write_private(&secrets_path(), serde_json::to_string(&contents)?.as_bytes())?;
+		Ok(())
+	}
+}
+
+/// Replaces `path` with `contents` by renaming a temp file in the same directory over it, so a
+/// crash never leaves it half written. On unix, only the owner can read it.
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+	let temp = path.with_extension("tmp");
+	std::fs::remove_file(&temp).ok();
+	let mut options = std::fs::OpenOptions::new();
+	options.write(true).create_new(true);
+	#[cfg(unix)]
+	std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+	let mut file = options.open(&temp)?;
+	file.write_all(contents)?;
+	file.sync_all()?;
+	std::fs::rename(&temp, path)
+}
+
+impl SecretStore for EncryptedFile {
+	fn describe(&self) -> &'static str {
+		"encrypted file"
+	}
+
+	fn get(&self, key: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+		let unlocked = UNLOCKED.lock().unwrap();
+		Ok(unlocked.as_ref().ok_or("secrets are locked")?.secrets.get(key).cloned())
+	}
+
+	fn set(&self, key: &str, value: &str) -> Result<(), Box<dyn std::error::Error>> {
+		let mut unlocked = UNLOCKED.lock().unwrap();
+		let unlocked = unlocked.as_mut().ok_or("secrets are locked")?;
+		let previous = match value {
+			"" => unlocked.secrets.remove(key),
+			value => unlocked.secrets.insert(key.to_owned(), value.to_owned()),
+		};
+		if let Err(e) = unlocked.save() {
+			match previous {
+				Some(previous) => unlocked.secrets.insert(key.to_owned(), previous),
+				None => unlocked.secrets.remove(key),
+			};
+			return Err(e);
+		}
+		Ok(())
+	}
+}
+
+fn use_keyring() -> bool {
+	settings::get("secret_store") == "keyring" && *KEYRING_AVAILABLE
+}
+
+/// Called when the `secret_store` setting changes.
+pub(crate) fn store_changed() {
+	*KEY_SAVED.lock().unwrap() = None;
+	MIGRATED.store(false, Ordering::SeqCst);
+}
+
+/// The configured store, or `None` if the encrypted file hasn't been unlocked yet.
+pub(crate) fn store() -> Option<Box<dyn SecretStore>> {
+	let store: Box<dyn SecretStore> = if use_keyring() {
+		Box::new(Keyring)
+	} else {
+		ENV_UNLOCK.call_once(|| {
+			if let Ok(passphrase) = std::env::var(PASSPHRASE_VAR)
+				&& !passphrase.is_empty()
+				&& let Err(e) = unlock(&passphrase)
+			{
+				eprintln!("{}: {}", PASSPHRASE_VAR, e);
+			}
+		});
+		UNLOCKED.lock().unwrap().as_ref()?;
+		Box::new(EncryptedFile)
+	};
+	if !MIGRATED.swap(true, Ordering::SeqCst) {
+		migrate_plaintext(store.as_ref());
+	}
+	Some(store)
+}
+
+/// Moves a key saved in plaintext by older versions into the secret store.
+fn migrate_plaintext(store: &dyn SecretStore) {
+	let mut setting = Setting::get(OPENAI_API_KEY);
+	if setting.value.is_empty() {
+		return;
+	}
+	match store.set(OPENAI_API_KEY, &setting.value) {
+		Ok(()) => {
+			setting.value.clear();
+			setting.save();
+			*KEY_SAVED.lock().unwrap() = None;
+		}
+		Err(e) => eprintln!("moving the api key to the {} failed: {}", store.describe(), e),
+	}
+}
+
+/// The OpenAI API key: `OPENAI_API_KEY` from the environment if set, otherwise from the store.
+/// While the encrypted file is locked, a key saved in plaintext by older versions still works.
+pub(crate) fn openai_api_key() -> String {
+	if let Ok(key) = std::env::var("OPENAI_API_KEY")
+		&& !key.is_empty()
+	{
+		return key;
+	}
+	let Some(store) = store() else {
+		let key = Setting::get(OPENAI_API_KEY).value;
+		if key.is_empty() {
+			eprintln!("secrets are locked; unlock them in settings or set {}", PASSPHRASE_VAR);
+		}
+		return key;
+	};
+	match store.get(OPENAI_API_KEY) {
+		Ok(Some(key)) => key,
+		// Migration failed, e.g. the keyring refused the write.
+		Ok(None) => Setting::get(OPENAI_API_KEY).value,
+		Err(e) => {
+			eprintln!("{}", e);
+			String::new()
+		}
+	}
+}
+
+/// Text typed into the settings panel but not yet saved.
+#[derive(Default)]
+pub(crate) struct SecretsUi {
+	api_key: String,
+	passphrase: String,
+	confirm: String,
+	error: Option<String>,
+}
+
+impl SecretsUi {
+	pub(crate) fn ui(&mut self, ui: &mut Ui) {
+		ui.label("openai api key:");
+
+		if std::env::var("OPENAI_API_KEY").is_ok_and(|k| !k.is_empty()) {
+			ui.label("using OPENAI_API_KEY from the environment");
+			return;
+		}
+
+		if settings::get("secret_store") == "keyring" && !*KEYRING_AVAILABLE {
+			ui.label("no OS keyring available; using the encrypted file");
+		}
+
+		let Some(store) = store() else {
+			// Without a file, any passphrase would unlock, so a typo would lock the key away.
+			let create = !file_exists();
+			ui.horizontal(|ui| {
+				let mut enter = false;
+				let response =
+					ui.add(TextEdit::singleline(&mut self.passphrase).password(true).hint_text(if create {
+						"new passphrase"
+					} else {
+						"passphrase"
+					}));
+				enter |= response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+				if create {
+					let response =
+						ui.add(TextEdit::singleline(&mut self.confirm).password(true).hint_text("repeat passphrase"));
+					enter |= response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+				}
+				if ui.button(if create { "create" } else { "unlock" }).clicked() || enter {
+					self.error = if create && self.passphrase != self.confirm {
+						Some("passphrases don't match".to_owned())
+					} else if create && self.passphrase.is_empty() {
+						Some("choose a passphrase".to_owned())
+					} else {
+						unlock(&self.passphrase).err().map(|e| e.to_string())
+					};
+					if self.error.is_none() || !create {
+						self.passphrase.clear();
+						self.confirm.clear();
+					}
+				}
+			});
+			if !Setting::get(OPENAI_API_KEY).value.is_empty() {
+				ui.label("using the key saved in plaintext until unlocked");
+			}
+			if let Some(error) = &self.error {
+				ui.colored_label(ui.visuals().error_fg_color, error);
+			}
+			return;
+		};
+
+		let saved = *KEY_SAVED.lock().unwrap().get_or_insert_with(|| {
+			store.get(OPENAI_API_KEY).ok().flatten().is_some_and(|key| !key.is_empty())
+		});
+
+		ui.horizontal(|ui| {
+			let response =
+				ui.add(TextEdit::singleline(&mut self.api_key).password(true).hint_text(if saved {
+					"saved; type to replace"
+				} else {
+					"sk-..."
+				}));
+			let save = ui.button("save").clicked()
+				|| (response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)));
+			let clear = saved && ui.button("clear").clicked();
+			if save || clear {
+				let value = if clear { String::new() } else { std::mem::take(&mut self.api_key) };
+				self.error = store.set(OPENAI_API_KEY, &value).err().map(|e| e.to_string());
+				*KEY_SAVED.lock().unwrap() = None;
+			}
+		});
+		ui.label(format!("in {}", store.describe()));
+
+		if let Some(error) = &self.error {
+			ui.colored_label(ui.visuals().error_fg_color, error);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::MutexGuard;
+
+	/// Points the database, and so the secrets file, at a temporary directory.
+	static DB_DIR: Lazy<tempfile::TempDir> = Lazy::new(|| {
+		let dir = tempfile::tempdir().unwrap();
+		turbosql::set_db_path(&dir.path().join("test.sqlite")).unwrap();
+		dir
+	});
+
+	/// Held by each test, since they share the secrets file and the unlocked secrets.
+	static LOCK: Mutex<()> = Mutex::new(());
+
+	fn fresh_file() -> MutexGuard<'static, ()> {
+		let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+		Lazy::force(&DB_DIR);
+		std::fs::remove_file(secrets_path()).ok();
+		*UNLOCKED.lock().unwrap() = None;
+		guard
+	}
+
+	#[test]
+	fn round_trip_through_the_file() {
+		let _guard = fresh_file();
+		unlock("correct horse").unwrap();
+		EncryptedFile.set(OPENAI_API_KEY, "sk-test").unwrap();
+
+		*UNLOCKED.lock().unwrap() = None;
+		assert!(EncryptedFile.get(OPENAI_API_KEY).is_err());
+		unlock("correct horse").unwrap();
+		assert_eq!(EncryptedFile.get(OPENAI_API_KEY).unwrap().as_deref(), Some("sk-test"));
+
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			let mode = std::fs::metadata(secrets_path()).unwrap().permissions().mode();
+			assert_eq!(mode & 0o777, 0o600);
+		}
+	}
+
+	#[test]
+	fn wrong_passphrase_does_not_unlock() {
+		let _guard = fresh_file();
+		unlock("correct horse").unwrap();
+		EncryptedFile.set(OPENAI_API_KEY, "sk-test").unwrap();
+
+		*UNLOCKED.lock().unwrap() = None;
+		let error = unlock("battery staple").unwrap_err();
+		assert!(error.to_string().contains("wrong passphrase"));
+		assert!(EncryptedFile.get(OPENAI_API_KEY).is_err());
+	}
+}
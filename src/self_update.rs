@@ -1,114 +1,693 @@
+use super::{settings, state, Setting};
+use egui::*;
 use futures::stream::StreamExt;
+use minisign_verify::{PublicKey, Signature};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use strategy::Strategy;
+use tokio::io::AsyncWriteExt;
+
+mod strategy;
+
+/// Release asset containing this platform's executable.
+const ASSET: &str = if cfg!(windows) { "brace.exe" } else { "brace" };
+
+/// minisign public key that release `SHA256SUMS` files are signed with, embedded at build time.
+/// CI may set it to an empty string, which also disables updates.
+const PUBLIC_KEY: Option<&str> = non_empty(option_env!("BRACE_UPDATE_PUBLIC_KEY"));
+
+/// Unix time this build was made, which releases must be newer than.
+const BUILD_TIME: Option<&str> = non_empty(option_env!("BUILD_TIME"));
+
+const fn non_empty(value: Option<&'static str>) -> Option<&'static str> {
+	match value {
+		Some(value) if !value.is_empty() => Some(value),
+		_ => None,
+	}
+}
+
+/// Latest result of checking for, installing or rolling back an update, shown in the side panel.
+#[derive(Clone, Default)]
+pub(crate) enum Status {
+	#[default]
+	Idle,
+	Checking,
+	UpToDate,
+	Available(Release),
+	Downloading {
+		version: String,
+		downloaded: u64,
+		total: u64,
+	},
+	/// The executable on disk was replaced; a restart runs the named version.
+	Installed(String),
+	/// An install was asked for while another update task was running.
+	Busy,
+	Failed(String),
+}
+
+fn set_status(events: &state::Sender, status: Status) {
+	events.send(state::Event::UpdateStatus(status)).ok();
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Policy {
+	Off,
+	Notify,
+	Auto,
+}
+
+impl Policy {
+	const ALL: [Self; 3] = [Self::Off, Self::Notify, Self::Auto];
+
+	fn name(self) -> &'static str {
+		match self {
+			Self::Off => "off",
+			Self::Notify => "notify",
+			Self::Auto => "auto",
+		}
+	}
+
+	pub(crate) fn get() -> Self {
+		let value = settings::get("update_policy");
+		Self::ALL.into_iter().find(|p| p.name() == value).unwrap_or(Self::Auto)
+	}
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Channel {
+	/// The most recent full release.
+	Stable,
+	/// The most recent release, including pre-releases.
+	Nightly,
+}
+
+impl Channel {
+	const ALL: [Self; 2] = [Self::Stable, Self::Nightly];
+
+	fn name(self) -> &'static str {
+		match self {
+			Self::Stable => "stable",
+			Self::Nightly => "nightly",
+		}
+	}
+
+	pub(crate) fn get() -> Self {
+		let value = settings::get("update_channel");
+		Self::ALL.into_iter().find(|c| c.name() == value).unwrap_or(Self::Stable)
+	}
+}
+
+/// The release last installed, which a rollback marks as skipped.
+const INSTALLED_KEY: &str = "update_installed_tag";
+
+/// A release that was rolled back, so it isn't offered or installed again.
+const SKIPPED_KEY: &str = "update_skipped_tag";
+
+#[derive(Clone, Deserialize)]
+pub(crate) struct Release {
+	pub(crate) tag_name: String,
+	#[serde(default)]
+	pub(crate) body: Option<String>,
+	assets: Vec<Asset>,
+}
+
+#[derive(Clone, Deserialize)]
+struct Asset {
+	name: String,
+	browser_download_url: String,
+}
+
+impl Release {
+	fn asset_url(&self, name: &str) -> Result<&str, String> {
+		self
+			.assets
+			.iter()
+			.find(|a| a.name == name)
+			.map(|a| a.browser_download_url.as_str())
+			.ok_or_else(|| format!("release {} has no {} asset", self.tag_name, name))
+	}
+}
+
+/// GitHub API base for releases; set `BRACE_UPDATE_API` to test against a local HTTP stand-in.
+fn release_api() -> String {
+	std::env::var("BRACE_UPDATE_API")
+		.unwrap_or_else(|_| "https://api.github.com/repos/trevyn/brace".into())
+}
+
+/// Detected once, since detection probes the install directory.
+static STRATEGY: Lazy<Result<Box<dyn Strategy>, String>> =
+	Lazy::new(|| Ok(strategy::detect(std::env::current_exe().map_err(|e| e.to_string())?)));
+
+fn strategy() -> Result<&'static dyn Strategy, String> {
+	STRATEGY.as_ref().map(|s| s.as_ref()).map_err(|e| e.clone())
+}
+
+/// Swaps in an update staged on a previous run; returns `true` if the caller should relaunch.
+pub(crate) fn apply_staged() -> Result<bool, std::io::Error> {
+	strategy::apply_staged(&std::env::current_exe()?)
+}
+
+/// Checks `signature` over `sums` against the embedded public key, that it was made for release
+/// `tag` and after this build, then that `bytes` hash to the SHA-256 listed for `asset` in `sums`.
+pub(crate) fn verify(
+	asset: &str,
+	bytes: &[u8],
+	sums: &str,
+	signature: &str,
+	tag: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+	let public_key = PUBLIC_KEY.ok_or("no update public key in this build")?;
+	let built = BUILD_TIME.unwrap_or("0").parse()?;
+	verify_with(public_key, built, asset, bytes, sums, signature, tag)
+}
+
+/// [`verify`] with the key and build time given explicitly.
+fn verify_with(
+	public_key: &str,
+	built: u64,
+	asset: &str,
+	bytes: &[u8],
+	sums: &str,
+	signature: &str,
+	tag: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+	let signature = Signature::decode(signature)?;
+	PublicKey::from_base64(public_key)?.verify(sums.as_bytes(), &signature, false)?;
+
+	// The trusted comment is covered by the signature, so an older signed release can't be passed
+	// off as this one, or replayed to downgrade.
+	let comment = signature.trusted_comment();
+	let (signed_tag, signed_time) = comment
+		.strip_prefix("brace ")
+		.and_then(|rest| rest.split_once(' '))
+		.ok_or_else(|| format!("unexpected trusted comment {:?}", comment))?;
+	if signed_tag != tag {
+		Err(format!("signature is for release {}, not {}", signed_tag, tag))?;
+	}
+	if signed_time.parse::<u64>()? <= built {
+		Err(format!("release {} is not newer than this build", tag))?;
+	}
+
+	let expected = sums
+		.lines()
+		.find_map(|line| {
+			let (hash, name) = line.split_once(char::is_whitespace)?;
+			(name.trim_start().trim_start_matches('*') == asset).then_some(hash)
+		})
+		.ok_or_else(|| format!("{} is not listed in SHA256SUMS", asset))?;
+	let actual = format!("{:x}", Sha256::digest(bytes));
+
+	if !actual.eq_ignore_ascii_case(expected) {
+		Err(format!("SHA-256 mismatch for {}: expected {}, got {}", asset, expected, actual))?;
+	}
 
-#[cfg(windows)]
-pub(crate) async fn self_update() -> Result<(), Box<dyn std::error::Error>> {
 	Ok(())
 }
 
-#[cfg(not(windows))]
-#[tracked::tracked]
-pub(crate) async fn self_update() -> Result<(), Box<dyn std::error::Error>> {
-	use std::os::unix::fs::OpenOptionsExt;
+/// Returns the newest release on the `update_channel`, or `None` if it's the one running or was
+/// rolled back.
+pub(crate) async fn check() -> Result<Option<Release>, Box<dyn std::error::Error>> {
+	let skipped = Setting::get(SKIPPED_KEY).value;
+	check_at(&release_api(), Channel::get(), &skipped).await
+}
+
+async fn check_at(
+	api: &str,
+	channel: Channel,
+	skipped: &str,
+) -> Result<Option<Release>, Box<dyn std::error::Error>> {
+	let client = reqwest::Client::builder().user_agent("brace").build()?;
+	let release = match channel {
+		Channel::Stable => {
+			let text =
+				client.get(format!("{}/releases/latest", api)).send().await?.error_for_status()?.text().await?;
+			serde_json::from_str::<Release>(&text)?
+		}
+		Channel::Nightly => {
+			let text = client
+				.get(format!("{}/releases?per_page=1", api))
+				.send()
+				.await?
+				.error_for_status()?
+				.text()
+				.await?;
+			serde_json::from_str::<Vec<Release>>(&text)?.into_iter().next().ok_or("no releases found")?
+		}
+	};
+
+	if option_env!("BUILD_ID").unwrap_or_default() == release.tag_name {
+		eprintln!("Running latest! {}", release.tag_name);
+		return Ok(None);
+	}
+	if release.tag_name == skipped {
+		eprintln!("Skipping rolled back release {}", release.tag_name);
+		return Ok(None);
+	}
+
+	Ok(Some(release))
+}
+
+/// Set while a check or install runs, so they never overlap.
+static IN_FLIGHT: AtomicBool = AtomicBool::new(false);
+
+/// Runs `task` in the background unless another update task is already running; returns whether
+/// it was started.
+fn spawn_exclusive(task: impl Future<Output = ()> + Send + 'static) -> bool {
+	if IN_FLIGHT.swap(true, Ordering::SeqCst) {
+		eprintln!("update already in progress");
+		return false;
+	}
+	tokio::spawn(async move {
+		task.await;
+		IN_FLIGHT.store(false, Ordering::SeqCst);
+	});
+	true
+}
+
+/// Runs [`self_update`] in the background.
+pub(crate) fn spawn(events: state::Sender) {
+	spawn_exclusive(async move {
+		self_update(&events).await.ok();
+	});
+}
 
+/// Checks for an update according to the `update_policy` and `update_channel` settings.
+pub(crate) async fn self_update(events: &state::Sender) -> Result<(), Box<dyn std::error::Error>> {
 	if option_env!("BUILD_ID").is_none() {
 		eprintln!("Running DEV; updates disabled.",);
 		return Ok(());
 	}
 
-	eprintln!("checking for updates...");
-
-	let res = reqwest::Client::builder()
-		.redirect(reqwest::redirect::Policy::none())
-		.build()?
-		.get("https://github.com/trevyn/brace/releases/latest/download/brace")
-		.send()
-		.await?;
+	if PUBLIC_KEY.is_none() || BUILD_TIME.is_none() {
+		eprintln!("No update public key or build time in this build; updates disabled.");
+		return Ok(());
+	}
 
-	if res.status() != 302 {
-		Err(format!("Err, HTTP status {}, expected 302 redirect", res.status()))?;
+	let policy = Policy::get();
+	if policy == Policy::Off {
+		return Ok(());
 	}
-	let location = res.headers().get(reqwest::header::LOCATION)?.to_str()?;
 
-	let new_version =
-		regex::Regex::new(r"/releases/download/([a-z]+-[a-z]+)/")?.captures(location)?.get(1)?.as_str();
+	set_status(events, Status::Checking);
+	let release = match check().await {
+		Ok(Some(release)) => release,
+		Ok(None) => {
+			set_status(events, Status::UpToDate);
+			return Ok(());
+		}
+		Err(e) => {
+			set_status(events, Status::Failed(e.to_string()));
+			return Err(e);
+		}
+	};
 
-	if option_env!("BUILD_ID").unwrap_or_default() == new_version {
-		eprintln!("Running latest! {}", new_version);
+	if policy == Policy::Notify {
+		set_status(events, Status::Available(release));
 		return Ok(());
 	}
 
-	eprintln!("downloading update {}...", new_version);
+	let result = install(&release, events).await;
+	let status = match &result {
+		Ok(()) => Status::Installed(release.tag_name.clone()),
+		Err(e) => Status::Failed(e.to_string()),
+	};
+	set_status(events, status);
+	result
+}
 
-	let res = reqwest::get(location).await?;
-	let total_size: usize = res.content_length()?.try_into()?;
+/// Downloads and verifies `release`, then hands it to the platform's install strategy.
+#[tracked::tracked]
+pub(crate) async fn install(
+	release: &Release,
+	events: &state::Sender,
+) -> Result<(), Box<dyn std::error::Error>> {
+	let new_version = release.tag_name.as_str();
+	let part = std::env::temp_dir().join(format!("brace-{}.part", new_version));
+	let bytes = fetch(release, &part, events, |bytes, sums, signature| {
+		verify(ASSET, bytes, sums, signature, new_version)
+	})
+	.await?;
 
-	if total_size < 10_000_000 {
-		eprintln!(
-			"Not updating; new release {} is unexpectedly small: {} bytes.",
-			new_version, total_size
-		);
-		return Ok(());
+	eprintln!("update {} verified, saving to disk...", new_version);
+
+	let strategy = strategy()?;
+	eprintln!("installing update {} {}", new_version, strategy.describe());
+	tokio::task::spawn_blocking(move || strategy.install(&bytes)).await??;
+	std::fs::remove_file(&part).ok();
+	let mut installed = Setting::get(INSTALLED_KEY);
+	installed.value = new_version.to_owned();
+	installed.save();
+	Ok(())
+}
+
+/// Downloads `release`'s executable into `part` along with its checksums and signature, and returns
+/// the executable if `verify` accepts them.
+async fn fetch(
+	release: &Release,
+	part: &Path,
+	events: &state::Sender,
+	verify: impl FnOnce(&[u8], &str, &str) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+	let new_version = release.tag_name.as_str();
+	let bytes = download(release.asset_url(ASSET)?, part, new_version, events).await?;
+
+	eprintln!("downloading update {} complete, {} bytes, verifying...", new_version, bytes.len());
+
+	let sums =
+		reqwest::get(release.asset_url("SHA256SUMS")?).await?.error_for_status()?.text().await?;
+	let signature =
+		reqwest::get(release.asset_url("SHA256SUMS.minisig")?).await?.error_for_status()?.text().await?;
+
+	if let Err(e) = verify(&bytes, &sums, &signature) {
+		std::fs::remove_file(part).ok();
+		Err(format!("Not updating; release {} failed verification: {}", new_version, e))?;
 	}
 
-	// if total_size > 50_000_000 {
-	// 	Err(format!(
-	// 		"Not updating; new release {} is unexpectedly large: {} bytes.",
-	// 		new_version, total_size
-	// 	))?;
-	// }
+	Ok(bytes)
+}
+
+/// Downloads `url` into the file `part`, resuming with a Range request from whatever a previous
+/// attempt or run left there, and reports progress to `events`.
+async fn download(
+	url: &str,
+	part: &Path,
+	version: &str,
+	events: &state::Sender,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+	const MAX_ATTEMPTS: u32 = 5;
+
+	let client = reqwest::Client::new();
+	let mut attempt = 0;
 
-	let mut bytes = Vec::with_capacity(total_size);
-	let mut stream = res.bytes_stream();
+	loop {
+		attempt += 1;
+		let offset = tokio::fs::metadata(part).await.map_or(0, |m| m.len());
+		let res =
+			client.get(url).header(reqwest::header::RANGE, format!("bytes={}-", offset)).send().await?;
 
-	while let Some(item) = stream.next().await {
-		bytes.extend_from_slice(&item?);
-		eprintln!(
-			"downloading update {} {}% {}/{}...",
-			new_version,
-			bytes.len() * 100 / total_size,
-			bytes.len(),
-			total_size
-		);
+		let (mut file, mut downloaded) = match res.status() {
+			reqwest::StatusCode::PARTIAL_CONTENT => {
+				(tokio::fs::OpenOptions::new().append(true).open(part).await?, offset)
+			}
+			reqwest::StatusCode::RANGE_NOT_SATISFIABLE if attempt < MAX_ATTEMPTS => {
+				tokio::fs::remove_file(part).await.ok();
+				continue;
+			}
+			status if status.is_success() => (tokio::fs::File::create(part).await?, 0),
+			status => Err(format!("Not updating; HTTP status {} downloading {}", status, version))?,
+		};
+
+		let total = downloaded + res.content_length().ok_or("missing content length")?;
+
+		if total < 10_000_000 {
+			Err(format!("Not updating; new release {} is unexpectedly small: {} bytes.", version, total))?;
+		}
+
+		let mut stream = res.bytes_stream();
+		let mut interrupted = None;
+
+		while let Some(item) = stream.next().await {
+			match item {
+				Ok(chunk) => {
+					file.write_all(&chunk).await?;
+					downloaded += chunk.len() as u64;
+					let status = Status::Downloading { version: version.to_owned(), downloaded, total };
+					set_status(events, status);
+				}
+				Err(e) => {
+					interrupted = Some(e.to_string());
+					break;
+				}
+			}
+		}
+
+		file.sync_all().await?;
+
+		match interrupted {
+			None if downloaded == total => return Ok(tokio::fs::read(part).await?),
+			None => Err(format!(
+				"Not updating; downloaded incorrect number of bytes: {} of {}.",
+				downloaded, total
+			))?,
+			Some(e) if attempt < MAX_ATTEMPTS => {
+				eprintln!(
+					"downloading update {} interrupted at {}/{}: {}; resuming",
+					version, downloaded, total, e
+				);
+				tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+			}
+			Some(e) => Err(format!("Not updating; download of {} failed: {}", version, e))?,
+		}
 	}
+}
 
-	let bytes = bytes;
+/// Launches a fresh copy of the executable on disk; the caller should then exit.
+fn restart() -> Result<(), std::io::Error> {
+	std::process::Command::new(std::env::current_exe()?).args(std::env::args_os().skip(1)).spawn()?;
+	Ok(())
+}
 
-	if bytes.len() != total_size {
-		eprintln!(
-			"Not updating; downloaded incorrect number of bytes: {} of {}.",
-			bytes.len(),
-			total_size
-		);
-		return Ok(());
-		// Err(format!(
-		// 	"Not updating; downloaded incorrect number of bytes: {} of {}.",
-		// 	bytes.len(),
-		// 	total_size
-		// ))?;
-	}
-
-	eprintln!("downloading update {} complete, {} bytes, saving to disk...", new_version, bytes.len());
-
-	// yield format!(
-	// 	"downloading update {} complete, {} bytes, saving to disk...",
-	// 	new_version,
-	// 	bytes.len()
-	// );
-
-	let current_exe = std::env::current_exe()?;
-	let current_exe_cloned = current_exe.clone();
-	let mut current_exe_update = current_exe.clone();
-	current_exe_update.set_extension("update")?;
-	let current_exe_update = current_exe_update;
-
-	tokio::task::spawn_blocking(move || -> Result<(), tracked::StringError> {
-		let mut f =
-			std::fs::OpenOptions::new().create(true).write(true).mode(0o700).open(&current_exe_update)?;
-		std::io::Write::write_all(&mut f, &bytes)?;
-		f.sync_all()?;
-		std::fs::rename(current_exe_update, current_exe_cloned)?;
-		Ok(())
-	})
-	.await??;
+/// Restores the binary replaced by the last install, taking effect on the next launch, and skips
+/// the release it replaced from then on, so automatic updates don't reinstall it.
+pub(crate) fn rollback() -> Result<(), Box<dyn std::error::Error>> {
+	strategy()?.rollback()?;
+	let installed = Setting::get(INSTALLED_KEY).value;
+	let mut skipped = Setting::get(SKIPPED_KEY);
+	skipped.value = match installed.as_str() {
+		"" => option_env!("BUILD_ID").unwrap_or_default().to_owned(),
+		installed => installed.to_owned(),
+	};
+	skipped.save();
 	Ok(())
 }
+
+/// Update status, release notes and the roll back action for the side panel.
+pub(crate) fn ui(ui: &mut Ui, store: &mut state::Store) {
+	match store.update_status.clone() {
+		Status::Idle => {}
+		Status::Checking => {
+			ui.horizontal(|ui| {
+				ui.spinner();
+				ui.label("checking for updates...");
+			});
+		}
+		Status::UpToDate => {
+			ui.label("up to date");
+		}
+		Status::Available(release) => {
+			ui.label(format!("update {} available", release.tag_name));
+			if let Some(body) = &release.body {
+				CollapsingHeader::new("release notes").show(ui, |ui| {
+					ui.label(body);
+				});
+			}
+			if ui.button("install update").clicked() {
+				let events = store.sender();
+				let started = spawn_exclusive(async move {
+					let result = install(&release, &events).await.map_err(|e| e.to_string());
+					let status = match result {
+						Ok(()) => Status::Installed(release.tag_name),
+						Err(e) => Status::Failed(e),
+					};
+					set_status(&events, status);
+				});
+				store.update_status = if started { Status::Checking } else { Status::Busy };
+			}
+		}
+		Status::Downloading { version, downloaded, total } => {
+			ui.label(format!("downloading update {}", version));
+			ui.add(ProgressBar::new(downloaded as f32 / total as f32).show_percentage().text(format!(
+				"{:.1}/{:.1} MB",
+				downloaded as f32 / 1e6,
+				total as f32 / 1e6
+			)));
+		}
+		Status::Installed(version) => {
+			ui.label(format!("{} is ready", version));
+			if ui.button("restart now").clicked() {
+				match restart() {
+					Ok(()) => ui.ctx().send_viewport_cmd(ViewportCommand::Close),
+					Err(e) => store.update_status = Status::Failed(e.to_string()),
+				}
+			}
+		}
+		Status::Busy => {
+			ui.label("update already in progress");
+		}
+		Status::Failed(e) => {
+			ui.colored_label(ui.visuals().error_fg_color, format!("update failed: {}", e));
+		}
+	}
+
+	if strategy().is_ok_and(|s| s.can_roll_back())
+		&& ui.button("roll back to previous version").clicked()
+	{
+		store.update_status = match rollback() {
+			Ok(()) => Status::Installed("previous version".into()),
+			Err(e) => Status::Failed(e.to_string()),
+		};
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use axum::extract::{Path as UrlPath, State};
+	use std::collections::HashMap;
+	use std::sync::Arc;
+
+	const TAG: &str = "test-release";
+
+	/// A pre-release newer than `TAG`, which only the nightly channel offers.
+	const NIGHTLY_TAG: &str = "test-nightly";
+
+	struct Signer(minisign::KeyPair);
+
+	impl Signer {
+		fn new() -> Self {
+			Self(minisign::KeyPair::generate_unencrypted_keypair().unwrap())
+		}
+
+		fn public_key(&self) -> String {
+			self.0.pk.to_base64()
+		}
+
+		fn sign(&self, data: &str, trusted_comment: &str) -> String {
+			minisign::sign(Some(&self.0.pk), &self.0.sk, data.as_bytes(), Some(trusted_comment), None)
+				.unwrap()
+				.into_string()
+		}
+	}
+
+	/// Serves release `TAG` with `files` as its assets, and pre-release `NIGHTLY_TAG` without any,
+	/// shaped like the GitHub API; returns the API base URL.
+	async fn serve(files: HashMap<String, Vec<u8>>) -> String {
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let base = format!("http://{}", listener.local_addr().unwrap());
+		let assets = files
+			.keys()
+			.map(|name| {
+				serde_json::json!({ "name": name, "browser_download_url": format!("{}/download/{}", base, name) })
+			})
+			.collect::<Vec<_>>();
+		let release = serde_json::json!({ "tag_name": TAG, "body": "notes", "assets": assets });
+		let nightly = serde_json::json!({ "tag_name": NIGHTLY_TAG, "prerelease": true, "assets": [] });
+		let releases = serde_json::json!([nightly, release]).to_string();
+		let release = release.to_string();
+
+		let app = axum::Router::new()
+			.route("/releases/latest", axum::routing::get(move || async move { release }))
+			.route("/releases", axum::routing::get(move || async move { releases }))
+			.route(
+				"/download/:name",
+				axum::routing::get(
+					|State(files): State<Arc<HashMap<String, Vec<u8>>>>, UrlPath(name): UrlPath<String>| async move {
+						files.get(&name).cloned().ok_or(axum::http::StatusCode::NOT_FOUND)
+					},
+				),
+			)
+			.with_state(Arc::new(files));
+		tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+		base
+	}
+
+	/// Publishes `exe` with `sums_of`'s checksum, signed by `signer` with `trusted_comment`, and
+	/// fetches it as a build made at `built` that trusts `public_key`.
+	async fn update(
+		exe: &[u8],
+		sums_of: &[u8],
+		signer: &Signer,
+		trusted_comment: &str,
+		public_key: &str,
+		built: u64,
+	) -> Result<Vec<u8>, String> {
+		let sums = format!("{:x}  {}\n", Sha256::digest(sums_of), ASSET);
+		let signature = signer.sign(&sums, trusted_comment);
+		let files = HashMap::from([
+			(ASSET.to_owned(), exe.to_vec()),
+			("SHA256SUMS".to_owned(), sums.into_bytes()),
+			("SHA256SUMS.minisig".to_owned(), signature.into_bytes()),
+		]);
+		let api = serve(files).await;
+
+		let release = check_at(&api, Channel::Stable, "").await.unwrap().unwrap();
+		assert_eq!(release.tag_name, TAG);
+		let dir = tempfile::tempdir().unwrap();
+		let (events, _status) = tokio::sync::mpsc::unbounded_channel();
+		fetch(&release, &dir.path().join("brace.part"), &events, |bytes, sums, signature| {
+			verify_with(public_key, built, ASSET, bytes, sums, signature, &release.tag_name)
+		})
+		.await
+		.map_err(|e| e.to_string())
+	}
+
+	/// `download` refuses anything smaller.
+	fn exe() -> Vec<u8> {
+		vec![7; 10_000_000]
+	}
+
+	fn comment(built: u64) -> String {
+		format!("brace {} {}", TAG, built)
+	}
+
+	#[tokio::test]
+	async fn accepts_signed_release() {
+		let signer = Signer::new();
+		let exe = exe();
+		let fetched = update(&exe, &exe, &signer, &comment(200), &signer.public_key(), 100).await;
+		assert_eq!(fetched.unwrap(), exe);
+	}
+
+	#[tokio::test]
+	async fn rejects_other_key() {
+		let (signer, trusted) = (Signer::new(), Signer::new());
+		let exe = exe();
+		let error = update(&exe, &exe, &signer, &comment(200), &trusted.public_key(), 100).await;
+		assert!(error.unwrap_err().contains("failed verification"));
+	}
+
+	#[tokio::test]
+	async fn rejects_hash_mismatch() {
+		let signer = Signer::new();
+		let error = update(&exe(), b"other", &signer, &comment(200), &signer.public_key(), 100).await;
+		assert!(error.unwrap_err().contains("SHA-256 mismatch"));
+	}
+
+	#[tokio::test]
+	async fn rejects_signature_for_other_release() {
+		let signer = Signer::new();
+		let exe = exe();
+		let error = update(&exe, &exe, &signer, "brace older 200", &signer.public_key(), 100).await;
+		assert!(error.unwrap_err().contains("not test-release"));
+	}
+
+	#[tokio::test]
+	async fn stable_offers_latest_release() {
+		let api = serve(HashMap::new()).await;
+		let release = check_at(&api, Channel::Stable, "").await.unwrap().unwrap();
+		assert_eq!(release.tag_name, TAG);
+		assert!(check_at(&api, Channel::Stable, TAG).await.unwrap().is_none());
+	}
+
+	#[tokio::test]
+	async fn nightly_offers_newest_prerelease() {
+		let api = serve(HashMap::new()).await;
+		let release = check_at(&api, Channel::Nightly, "").await.unwrap().unwrap();
+		assert_eq!(release.tag_name, NIGHTLY_TAG);
+		assert!(check_at(&api, Channel::Nightly, NIGHTLY_TAG).await.unwrap().is_none());
+	}
+
+	#[tokio::test]
+	async fn rejects_downgrade() {
+		let signer = Signer::new();
+		let exe = exe();
+		let error = update(&exe, &exe, &signer, &comment(100), &signer.public_key(), 100).await;
+		assert!(error.unwrap_err().contains("not newer"));
+	}
+}
@@ -0,0 +1,265 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// How a downloaded release replaces the running executable.
+pub(crate) trait Strategy: Send + Sync {
+	fn describe(&self) -> String;
+
+	/// Puts `bytes` in place so that the next launch runs them.
+	fn install(&self, bytes: &[u8]) -> io::Result<()>;
+
+	fn can_roll_back(&self) -> bool;
+
+	/// Arranges for the next launch to run the binary that was replaced by the last install.
+	fn rollback(&self) -> io::Result<()>;
+}
+
+/// Picks a strategy for the executable at `exe`.
+pub(crate) fn detect(exe: PathBuf) -> Box<dyn Strategy> {
+	detect_for(exe, cfg!(windows))
+}
+
+/// Like [`detect`], but with the platform given explicitly so every branch can be exercised on any OS.
+pub(crate) fn detect_for(exe: PathBuf, windows: bool) -> Box<dyn Strategy> {
+	let dir = exe.parent().unwrap_or(Path::new(".")).to_owned();
+
+	if !is_writable(&dir) {
+		Box::new(Unwritable { dir })
+	} else if windows || in_app_bundle(&exe) {
+		Box::new(Staged { exe })
+	} else {
+		Box::new(InPlace { exe })
+	}
+}
+
+/// Swaps in a binary staged by [`Staged`] on a previous run. Returns `true` if one was applied,
+/// in which case the caller should relaunch.
+pub(crate) fn apply_staged(exe: &Path) -> io::Result<bool> {
+	let staged = staged_path(exe);
+	if !staged.exists() {
+		return Ok(false);
+	}
+	// Renaming a running executable is allowed everywhere, unlike overwriting it.
+	std::fs::rename(exe, backup_path(exe))?;
+	std::fs::rename(staged, exe)?;
+	Ok(true)
+}
+
+pub(crate) fn backup_path(exe: &Path) -> PathBuf {
+	exe.with_extension("bak")
+}
+
+fn staged_path(exe: &Path) -> PathBuf {
+	exe.with_extension("staged")
+}
+
+fn is_writable(dir: &Path) -> bool {
+	let probe = dir.join(".brace-write-test");
+	let writable = std::fs::OpenOptions::new().write(true).create_new(true).open(&probe).is_ok();
+	if writable {
+		std::fs::remove_file(probe).ok();
+	}
+	writable
+}
+
+/// `true` for executables inside a macOS bundle, i.e. `Foo.app/Contents/MacOS/foo`.
+fn in_app_bundle(exe: &Path) -> bool {
+	let mut ancestors = exe.ancestors().skip(1);
+	ancestors.next().is_some_and(|p| p.ends_with("MacOS"))
+		&& ancestors.next().is_some_and(|p| p.ends_with("Contents"))
+		&& ancestors.next().and_then(|p| p.extension()).is_some_and(|e| e == "app")
+}
+
+fn write_executable(path: &Path, bytes: &[u8]) -> io::Result<()> {
+	let mut options = std::fs::OpenOptions::new();
+	options.create(true).write(true).truncate(true);
+	#[cfg(unix)]
+	std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o700);
+	let mut f = options.open(path)?;
+	f.write_all(bytes)?;
+	f.sync_all()
+}
+
+/// Atomically renames the new binary over the running one, keeping the old one as `.bak`.
+pub(crate) struct InPlace {
+	pub(crate) exe: PathBuf,
+}
+
+impl Strategy for InPlace {
+	fn describe(&self) -> String {
+		format!("in place at {}", self.exe.display())
+	}
+
+	fn install(&self, bytes: &[u8]) -> io::Result<()> {
+		let update = self.exe.with_extension("update");
+		write_executable(&update, bytes)?;
+		std::fs::copy(&self.exe, backup_path(&self.exe))?;
+		std::fs::rename(update, &self.exe)
+	}
+
+	fn can_roll_back(&self) -> bool {
+		backup_path(&self.exe).exists()
+	}
+
+	fn rollback(&self) -> io::Result<()> {
+		std::fs::rename(backup_path(&self.exe), &self.exe)
+	}
+}
+
+/// Writes the new binary next to the running one and swaps it in with [`apply_staged`] at the
+/// next launch, for platforms and bundles where the running executable mustn't be replaced.
+pub(crate) struct Staged {
+	pub(crate) exe: PathBuf,
+}
+
+impl Strategy for Staged {
+	fn describe(&self) -> String {
+		format!("staged for next launch at {}", staged_path(&self.exe).display())
+	}
+
+	fn install(&self, bytes: &[u8]) -> io::Result<()> {
+		write_executable(&staged_path(&self.exe), bytes)
+	}
+
+	fn can_roll_back(&self) -> bool {
+		backup_path(&self.exe).exists()
+	}
+
+	fn rollback(&self) -> io::Result<()> {
+		std::fs::copy(backup_path(&self.exe), staged_path(&self.exe))?;
+		Ok(())
+	}
+}
+
+/// The install location can't be written to, e.g. a translocated macOS app or a system directory.
+pub(crate) struct Unwritable {
+	pub(crate) dir: PathBuf,
+}
+
+impl Unwritable {
+	fn error(&self) -> io::Error {
+		io::Error::new(
+			io::ErrorKind::PermissionDenied,
+			format!("{} is not writable; download the update manually", self.dir.display()),
+		)
+	}
+}
+
+impl Strategy for Unwritable {
+	fn describe(&self) -> String {
+		format!("{} is read-only; updates must be installed manually", self.dir.display())
+	}
+
+	fn install(&self, _bytes: &[u8]) -> io::Result<()> {
+		Err(self.error())
+	}
+
+	fn can_roll_back(&self) -> bool {
+		false
+	}
+
+	fn rollback(&self) -> io::Result<()> {
+		Err(self.error())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn exe_in(dir: &Path, contents: &str) -> PathBuf {
+		let exe = dir.join("brace");
+		std::fs::write(&exe, contents).unwrap();
+		exe
+	}
+
+	#[test]
+	fn in_place_installs_and_rolls_back() {
+		let dir = tempfile::tempdir().unwrap();
+		let exe = exe_in(dir.path(), "old");
+		let strategy = detect_for(exe.clone(), false);
+		assert!(strategy.describe().starts_with("in place"));
+		assert!(!strategy.can_roll_back());
+
+		strategy.install(b"new").unwrap();
+		assert_eq!(std::fs::read_to_string(&exe).unwrap(), "new");
+		assert_eq!(std::fs::read_to_string(backup_path(&exe)).unwrap(), "old");
+		assert!(!exe.with_extension("update").exists());
+
+		assert!(strategy.can_roll_back());
+		strategy.rollback().unwrap();
+		assert_eq!(std::fs::read_to_string(&exe).unwrap(), "old");
+		assert!(!backup_path(&exe).exists());
+	}
+
+	#[test]
+	fn staged_on_windows() {
+		let dir = tempfile::tempdir().unwrap();
+		let exe = exe_in(dir.path(), "old");
+		let strategy = detect_for(exe.clone(), true);
+		assert!(strategy.describe().starts_with("staged"));
+
+		strategy.install(b"new").unwrap();
+		assert_eq!(std::fs::read_to_string(&exe).unwrap(), "old");
+		assert_eq!(std::fs::read_to_string(staged_path(&exe)).unwrap(), "new");
+	}
+
+	#[test]
+	fn apply_staged_swaps_and_cleans_up() {
+		let dir = tempfile::tempdir().unwrap();
+		let exe = exe_in(dir.path(), "old");
+		assert!(!apply_staged(&exe).unwrap());
+
+		detect_for(exe.clone(), true).install(b"new").unwrap();
+		assert!(apply_staged(&exe).unwrap());
+		assert_eq!(std::fs::read_to_string(&exe).unwrap(), "new");
+		assert_eq!(std::fs::read_to_string(backup_path(&exe)).unwrap(), "old");
+		assert!(!staged_path(&exe).exists());
+		assert!(!apply_staged(&exe).unwrap());
+
+		// Rolling back stages the old binary for the next launch.
+		let strategy = detect_for(exe.clone(), true);
+		assert!(strategy.can_roll_back());
+		strategy.rollback().unwrap();
+		assert!(apply_staged(&exe).unwrap());
+		assert_eq!(std::fs::read_to_string(&exe).unwrap(), "old");
+	}
+
+	#[test]
+	fn staged_in_app_bundle() {
+		let dir = tempfile::tempdir().unwrap();
+		let macos = dir.path().join("Brace.app/Contents/MacOS");
+		std::fs::create_dir_all(&macos).unwrap();
+		let exe = exe_in(&macos, "old");
+		assert!(in_app_bundle(&exe));
+		assert!(detect_for(exe, false).describe().starts_with("staged"));
+
+		let not_bundle = dir.path().join("Brace/Contents/MacOS");
+		std::fs::create_dir_all(&not_bundle).unwrap();
+		let exe = exe_in(&not_bundle, "old");
+		assert!(!in_app_bundle(&exe));
+		assert!(detect_for(exe, false).describe().starts_with("in place"));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn unwritable_directory() {
+		use std::os::unix::fs::PermissionsExt;
+
+		let dir = tempfile::tempdir().unwrap();
+		let exe = exe_in(dir.path(), "old");
+		std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o555)).unwrap();
+		let writable = is_writable(dir.path());
+		let strategy = detect_for(exe.clone(), false);
+		std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o755)).unwrap();
+		if writable {
+			eprintln!("skipping: permissions aren't enforced for this user");
+			return;
+		}
+
+		assert!(strategy.describe().contains("read-only"));
+		assert!(!strategy.can_roll_back());
+		assert_eq!(strategy.install(b"new").unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+		assert_eq!(std::fs::read_to_string(&exe).unwrap(), "old");
+	}
+}
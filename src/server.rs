@@ -0,0 +1,343 @@
+use super::*;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use futures::stream::{self, StreamExt as _};
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+
+/// Shuts the running server down when dropped.
+static SERVER: Lazy<Mutex<Option<Trigger>>> = Lazy::new(Default::default);
+
+const TOKEN_KEY: &str = "api_token";
+
+/// The bearer token every request must carry, generated on first use.
+static TOKEN: Lazy<Mutex<String>> = Lazy::new(|| {
+	let mut setting = Setting::get(TOKEN_KEY);
+	if setting.value.is_empty() {
+		setting.value = new_token();
+		setting.save();
+	}
+	Mutex::new(setting.value)
+});
+
+fn new_token() -> String {
+	let mut bytes = [0u8; 32];
+	OsRng.fill_bytes(&mut bytes);
+	bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Shows the token clients must send, with buttons to copy and replace it.
+pub(crate) fn token_ui(ui: &mut Ui) {
+	ui.horizontal(|ui| {
+		ui.label("api server token:");
+		let token = TOKEN.lock().unwrap().clone();
+		ui.label(RichText::new(&token).monospace());
+		if ui.small_button("📋").on_hover_text("copy").clicked() {
+			ui.output_mut(|o| o.copied_text = token);
+		}
+		if ui
+			.small_button("regenerate")
+			.on_hover_text("clients using the old token stop working")
+			.clicked()
+		{
+			let mut setting = Setting::get(TOKEN_KEY);
+			setting.value = new_token();
+			setting.save();
+			*TOKEN.lock().unwrap() = setting.value;
+		}
+	});
+	ui.label(
+		RichText::new(
+			"Clients send it as \"Authorization: Bearer <token>\", e.g. as an OpenAI client's API key.",
+		)
+		.weak(),
+	);
+}
+
+/// Stops the server if it's running, then starts it again if the `api_server` setting is on.
+pub(crate) fn restart(events: &state::Sender) {
+	let mut server = SERVER.lock().unwrap();
+	*server = None;
+
+	if settings::get("api_server") != "true" {
+		return;
+	}
+
+	let port = settings::get("api_port").parse().unwrap();
+	let (trigger, tripwire) = Tripwire::new();
+	*server = Some(trigger);
+	let events = events.clone();
+	tokio::spawn(async move {
+		if let Err(e) = serve(port, events, tripwire).await {
+			eprintln!("api server: {}", e);
+		}
+	});
+}
+
+async fn serve(port: u16, events: state::Sender, tripwire: Tripwire) -> std::io::Result<()> {
+	let app = Router::new()
+		.route("/v1/chat/completions", post(chat_completions))
+		.route("/documents", get(documents))
+		.route("/documents/:rowid", get(document))
+		.route("/windows", get(windows))
+		.route("/windows/:window/messages", post(append_message))
+		.route("/prompts", get(prompts))
+		.layer(middleware::from_fn_with_state(port, authorize))
+		.with_state(events);
+
+	// Only bound to localhost, but web pages can still reach it through DNS rebinding, so
+	// `authorize` checks every request.
+	let listener = tokio::net::TcpListener::bind(("127.0.0.1", port)).await?;
+	eprintln!("api server listening on http://{}", listener.local_addr()?);
+
+	axum::serve(listener, app)
+		.with_graceful_shutdown(async move {
+			tripwire.await;
+		})
+		.await
+}
+
+/// Rejects requests without the token, and requests for any host other than this server's, as a
+/// DNS-rebinding page's would be.
+async fn authorize(
+	State(port): State<u16>,
+	request: Request,
+	next: Next,
+) -> Result<Response, ApiError> {
+	let host = request
+		.headers()
+		.get(header::HOST)
+		.and_then(|host| host.to_str().ok())
+		.or(request.uri().authority().map(|authority| authority.as_str()))
+		.unwrap_or_default();
+	if host != format!("127.0.0.1:{}", port) && host != format!("localhost:{}", port) {
+		return Err(ApiError(StatusCode::FORBIDDEN, format!("unexpected host {:?}", host)));
+	}
+
+	let token = request
+		.headers()
+		.get(header::AUTHORIZATION)
+		.and_then(|value| value.to_str().ok())
+		.and_then(|value| value.strip_prefix("Bearer "))
+		.unwrap_or_default();
+	if !constant_time_eq(token.as_bytes(), TOKEN.lock().unwrap().as_bytes()) {
+		return Err(ApiError(StatusCode::UNAUTHORIZED, "missing or wrong bearer token".into()));
+	}
+
+	Ok(next.run(request).await)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// An error in the OpenAI error shape, so OpenAI clients report it sensibly.
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+	fn into_response(self) -> Response {
+		(self.0, Json(json!({ "error": { "message": self.1 } }))).into_response()
+	}
+}
+
+fn bad_request(e: impl ToString) -> ApiError {
+	ApiError(StatusCode::BAD_REQUEST, e.to_string())
+}
+
+fn internal(e: impl ToString) -> ApiError {
+	ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+fn not_found(what: String) -> ApiError {
+	ApiError(StatusCode::NOT_FOUND, what)
+}
+
+/// Sends an event to the UI thread and waits for its reply. The UI only applies events while
+/// it's drawing frames, hence the timeout.
+async fn ask_ui<T>(
+	events: &state::Sender,
+	event: impl FnOnce(oneshot::Sender<T>) -> state::Event,
+) -> Result<T, ApiError> {
+	let unavailable =
+		|| ApiError(StatusCode::SERVICE_UNAVAILABLE, "brace's window isn't responding".into());
+	let (reply, rx) = oneshot::channel();
+	events.send(event(reply)).map_err(|_| unavailable())?;
+	match tokio::time::timeout(Duration::from_secs(5), rx).await {
+		Ok(Ok(value)) => Ok(value),
+		_ => Err(unavailable()),
+	}
+}
+
+#[derive(Deserialize)]
+struct ChatRequest {
+	messages: Value,
+	model: Option<String>,
+	temperature: Option<f32>,
+	top_p: Option<f32>,
+	max_tokens: Option<u16>,
+	seed: Option<i64>,
+	#[serde(default)]
+	stream: bool,
+}
+
+/// Runs a chat completion through `run_openai`, with any parameters not in the request
+/// taken from the stored settings.
+async fn chat_completions(Json(request): Json<ChatRequest>) -> Result<Response, ApiError> {
+	let messages = transcript::parse_messages(request.messages).map_err(bad_request)?;
+
+	let mut params = GenerationParams::from_settings();
+	params.model = request.model.unwrap_or(params.model);
+	params.temperature = request.temperature.unwrap_or(params.temperature);
+	params.top_p = request.top_p.unwrap_or(params.top_p);
+	params.max_tokens = request.max_tokens.unwrap_or(params.max_tokens);
+	params.seed = request.seed.or(params.seed);
+
+	let id = format!("chatcmpl-brace-{}", now_ms());
+	let created = now_ms() / 1000;
+	let model = params.model.clone();
+
+	// Dropping the trigger, e.g. when the client disconnects, cancels the completion.
+	let (trigger, tripwire) = Tripwire::new();
+	let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Result<String, String>>();
+	let error_tx = tx.clone();
+	tokio::spawn(async move {
+		let result = run_openai(params, tripwire, messages, move |content| {
+			tx.send(Ok(content.clone())).ok();
+		})
+		.await;
+		if let Err(e) = result {
+			error_tx.send(Err(e.to_string())).ok();
+		}
+	});
+
+	if !request.stream {
+		let _trigger = trigger;
+		let mut content = String::new();
+		while let Some(delta) = rx.recv().await {
+			content.push_str(&delta.map_err(internal)?);
+		}
+		return Ok(
+			Json(json!({
+				"id": id,
+				"object": "chat.completion",
+				"created": created,
+				"model": model,
+				"choices": [{
+					"index": 0,
+					"message": { "role": "assistant", "content": content },
+					"finish_reason": "stop",
+				}],
+			}))
+			.into_response(),
+		);
+	}
+
+	let first = chunk(&id, created, &model, json!({ "role": "assistant" }), None);
+	let last = chunk(&id, created, &model, json!({}), Some("stop"));
+	let deltas = stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|delta| (delta, rx)) });
+	let events = stream::iter([first])
+		.chain(deltas.map(move |delta| {
+			let _trigger = &trigger;
+			match delta {
+				Ok(content) => chunk(&id, created, &model, json!({ "content": content }), None),
+				Err(e) => Event::default().json_data(json!({ "error": { "message": e } })).unwrap(),
+			}
+		}))
+		.chain(stream::iter([last, Event::default().data("[DONE]")]))
+		.map(Ok::<_, std::convert::Infallible>);
+
+	Ok(Sse::new(events).into_response())
+}
+
+fn chunk(id: &str, created: i64, model: &str, delta: Value, finish_reason: Option<&str>) -> Event {
+	Event::default()
+		.json_data(json!({
+			"id": id,
+			"object": "chat.completion.chunk",
+			"created": created,
+			"model": model,
+			"choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+		}))
+		.unwrap()
+}
+
+async fn documents() -> Result<Json<Value>, ApiError> {
+	let documents = select!(Vec<Document> "ORDER BY timestamp_ms DESC").map_err(internal)?;
+	Ok(Json(
+		documents
+			.iter()
+			.map(|d| json!({ "rowid": d.rowid, "title": d.title, "timestamp_ms": d.timestamp_ms }))
+			.collect(),
+	))
+}
+
+async fn document(Path(rowid): Path<i64>) -> Result<Json<Value>, ApiError> {
+	let document = select!(Option<Document> "WHERE rowid = " rowid)
+		.map_err(internal)?
+		.ok_or_else(|| not_found(format!("no document {}", rowid)))?;
+	Ok(Json(json!({
+		"rowid": document.rowid,
+		"title": document.title,
+		"timestamp_ms": document.timestamp_ms,
+		"content": document.content,
+	})))
+}
+
+async fn windows(State(events): State<state::Sender>) -> Result<Json<Value>, ApiError> {
+	let windows = ask_ui(&events, |reply| state::Event::ListWindows { reply }).await?;
+	Ok(Json(json!(windows)))
+}
+
+#[derive(Deserialize)]
+struct AppendRequest {
+	/// Defaults to `user`.
+	#[serde(default)]
+	role: Role,
+	content: String,
+}
+
+/// Adds a message to a wheel window, before the empty prompt at the end if there is one.
+async fn append_message(
+	State(events): State<state::Sender>,
+	Path(window): Path<u64>,
+	Json(request): Json<AppendRequest>,
+) -> Result<Json<Value>, ApiError> {
+	transcript::check_role(request.role).map_err(bad_request)?;
+
+	let message = ChatMessage::new(request.role, request.content);
+	let index =
+		ask_ui(&events, |reply| state::Event::AppendMessage { window: WindowId(window), message, reply })
+			.await?
+			.ok_or_else(|| not_found(format!("no wheel window {}", window)))?;
+
+	Ok(Json(json!({ "window": window, "index": index })))
+}
+
+#[derive(Deserialize)]
+struct PromptQuery {
+	#[serde(default)]
+	q: String,
+	#[serde(default = "default_limit")]
+	limit: i64,
+}
+
+fn default_limit() -> i64 {
+	20
+}
+
+/// Past prompts containing `q`, newest first.
+async fn prompts(Query(query): Query<PromptQuery>) -> Result<Json<Value>, ApiError> {
+	let prompts = search::prompts(&query.q, query.limit).map_err(internal)?;
+	Ok(Json(
+		prompts
+			.iter()
+			.map(|p| json!({ "rowid": p.rowid, "time_ms": p.time_ms, "prompt": p.prompt }))
+			.collect(),
+	))
+}
@@ -0,0 +1,397 @@
+use super::Setting;
+use egui::*;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use tokio::sync::broadcast;
+
+pub(crate) enum Kind {
+	Text,
+	/// One entry per line.
+	Lines,
+	Choice(&'static [&'static str]),
+	Bool,
+	Integer {
+		min: i64,
+		max: i64,
+	},
+	/// A file path, or empty for none.
+	File,
+	/// `#rrggbb`, or empty for the default.
+	Color,
+}
+
+pub(crate) struct Spec {
+	pub(crate) key: &'static str,
+	pub(crate) kind: Kind,
+	pub(crate) default: &'static str,
+	pub(crate) description: &'static str,
+}
+
+/// Every user-facing setting. Secrets live in `secrets`, not here.
+pub(crate) const REGISTRY: &[Spec] = &[
+	Spec {
+		key: "openai_model",
+		kind: Kind::Text,
+		default: "gpt-4o-mini",
+		description: "Model used by new wheel windows.",
+	},
+	Spec {
+		key: "compare_models",
+		kind: Kind::Lines,
+		default: "gpt-4o-mini\ngpt-4o",
+		description: "Models in a new comparison window, one per line.",
+	},
+	Spec {
+		key: "finetune_max_tokens",
+		kind: Kind::Integer { min: 1, max: 1_000_000 },
+		default: "65536",
+		description: "Longest fine-tuning example, in tokens, that the dataset export accepts.",
+	},
+	Spec {
+		key: "secret_store",
+		kind: Kind::Choice(&["keyring", "file"]),
+		default: "keyring",
+		description: "Where the API key is kept: the OS keyring or a passphrase-encrypted file, which BRACE_PASSPHRASE unlocks for the CLI.",
+	},
+	Spec {
+		key: "api_server",
+		kind: Kind::Bool,
+		default: "false",
+		description: "Serve an OpenAI-compatible API and brace's documents, windows and prompts on localhost. Any local program given the api server token can then use your API key.",
+	},
+	Spec {
+		key: "api_port",
+		kind: Kind::Integer { min: 1024, max: 65535 },
+		default: "8737",
+		description: "Port for the local API server.",
+	},
+	Spec {
+		key: "layout",
+		kind: Kind::Choice(&["tiled", "floating"]),
+		default: "floating",
+		description: "Tabs and splits in the main area, or free-floating windows.",
+	},
+	Spec {
+		key: "theme",
+		kind: Kind::Choice(&["system", "dark", "light"]),
+		default: "system",
+		description: "Dark or light, or whichever the OS uses where that can be detected.",
+	},
+	Spec {
+		key: "ui_zoom",
+		kind: Kind::Integer { min: 50, max: 300 },
+		default: "100",
+		description: "Scale of the whole interface, in percent.",
+	},
+	Spec {
+		key: "editor_font",
+		kind: Kind::Choice(&["monospace", "proportional", "custom"]),
+		default: "monospace",
+		description: "Font of message editors. Custom is the custom_font file.",
+	},
+	Spec {
+		key: "editor_font_size",
+		kind: Kind::Integer { min: 6, max: 72 },
+		default: "20",
+		description: "Size of message editors' font, in points.",
+	},
+	Spec {
+		key: "document_font",
+		kind: Kind::Choice(&["monospace", "proportional", "custom"]),
+		default: "proportional",
+		description: "Font of large free-form editors.",
+	},
+	Spec {
+		key: "document_font_size",
+		kind: Kind::Integer { min: 6, max: 96 },
+		default: "30",
+		description: "Size of large free-form editors' font, in points.",
+	},
+	Spec {
+		key: "custom_font",
+		kind: Kind::File,
+		default: "",
+		description: "A TrueType or OpenType font file for the custom font choice.",
+	},
+	Spec {
+		key: "user_color",
+		kind: Kind::Color,
+		default: "",
+		description: "Text color of user messages. Empty follows the theme.",
+	},
+	Spec {
+		key: "assistant_color",
+		kind: Kind::Color,
+		default: "",
+		description: "Text color of assistant messages. Empty follows the theme.",
+	},
+	Spec {
+		key: "system_color",
+		kind: Kind::Color,
+		default: "",
+		description: "Text color of system messages. Empty follows the theme.",
+	},
+	Spec {
+		key: "keymap_preset",
+		kind: Kind::Choice(&["default", "vim", "emacs"]),
+		default: "default",
+		description: "Keyboard shortcuts to start from, including cursor movement in message editors. Rebind them from the keyboard shortcuts window.",
+	},
+	Spec {
+		key: "update_policy",
+		kind: Kind::Choice(&["off", "notify", "auto"]),
+		default: "auto",
+		description: "Whether to check for updates, and whether to install them without asking.",
+	},
+	Spec {
+		key: "update_channel",
+		kind: Kind::Choice(&["stable", "nightly"]),
+		default: "stable",
+		description: "Nightly also offers pre-releases.",
+	},
+];
+
+/// Keys of settings that changed, for subsystems that react without a restart.
+static CHANGES: Lazy<broadcast::Sender<&'static str>> = Lazy::new(|| broadcast::channel(64).0);
+
+pub(crate) fn subscribe() -> broadcast::Receiver<&'static str> {
+	CHANGES.subscribe()
+}
+
+fn spec(key: &str) -> Result<&'static Spec, String> {
+	REGISTRY.iter().find(|s| s.key == key).ok_or_else(|| format!("unknown setting {}", key))
+}
+
+impl Spec {
+	pub(crate) fn validate(&self, value: &str) -> Result<(), String> {
+		match self.kind {
+			Kind::Text if value.trim().is_empty() => Err("must not be empty".into()),
+			Kind::Lines if value.lines().all(|l| l.trim().is_empty()) => {
+				Err("needs at least one line".into())
+			}
+			Kind::Choice(choices) if !choices.contains(&value) => {
+				Err(format!("must be one of {}", choices.join(", ")))
+			}
+			Kind::Bool if value != "true" && value != "false" => Err("must be true or false".into()),
+			Kind::File if !value.is_empty() && !std::path::Path::new(value).is_file() => {
+				Err("no such file".into())
+			}
+			Kind::Color if !value.is_empty() && super::theme::parse_color(value).is_none() => {
+				Err("must be #rrggbb, or empty for the default".into())
+			}
+			Kind::Integer { min, max } => match value.parse::<i64>() {
+				Ok(n) if (min..=max).contains(&n) => Ok(()),
+				_ => Err(format!("must be a whole number from {} to {}", min, max)),
+			},
+			_ => Ok(()),
+		}
+	}
+
+	fn to_toml(&self, value: &str) -> toml::Value {
+		match self.kind {
+			Kind::Lines => toml::Value::Array(value.lines().map(|l| l.to_owned().into()).collect()),
+			Kind::Bool => toml::Value::Boolean(value == "true"),
+			Kind::Integer { .. } => value.parse().map_or_else(|_| value.into(), toml::Value::Integer),
+			Kind::Text | Kind::Choice(_) | Kind::File | Kind::Color => value.into(),
+		}
+	}
+
+	fn from_toml(&self, value: &toml::Value) -> Result<String, String> {
+		let value = match (&self.kind, value) {
+			(Kind::Lines, toml::Value::Array(lines)) => lines
+				.iter()
+				.map(|l| l.as_str().map(str::to_owned).ok_or("lines must be strings"))
+				.collect::<Result<Vec<_>, _>>()?
+				.join("\n"),
+			(Kind::Bool, toml::Value::Boolean(b)) => b.to_string(),
+			(Kind::Integer { .. }, toml::Value::Integer(n)) => n.to_string(),
+			(_, toml::Value::String(s)) => s.clone(),
+			(_, value) => Err(format!("unexpected {}", value.type_str()))?,
+		};
+		self.validate(&value)?;
+		Ok(value)
+	}
+}
+
+/// The stored value of `key`, or its default if unset or no longer valid. Keys missing from
+/// [`REGISTRY`] read as empty.
+pub(crate) fn get(key: &str) -> String {
+	let spec = match spec(key) {
+		Ok(spec) => spec,
+		Err(e) => {
+			debug_assert!(false, "{}", e);
+			eprintln!("{}", e);
+			return String::new();
+		}
+	};
+	let value = Setting::get_with_default(key, spec.default).value;
+	match spec.validate(&value) {
+		Ok(()) => value,
+		Err(_) => spec.default.to_owned(),
+	}
+}
+
+/// Validates and stores `value`, notifying subscribers if it changed.
+pub(crate) fn set(key: &str, value: &str) -> Result<(), String> {
+	let spec = spec(key)?;
+	spec.validate(value).map_err(|e| format!("{}: {}", key, e))?;
+	let mut setting = Setting::get(key);
+	if setting.value != value {
+		setting.value = value.to_owned();
+		setting.save();
+		CHANGES.send(spec.key).ok();
+	}
+	Ok(())
+}
+
+pub(crate) fn export_toml() -> String {
+	let table = REGISTRY.iter().map(|spec| (spec.key.to_owned(), spec.to_toml(&get(spec.key))));
+	toml::to_string_pretty(&table.collect::<toml::Table>()).unwrap()
+}
+
+/// Applies every valid setting in `text`; returns an error per key that was rejected.
+pub(crate) fn import_toml(text: &str) -> Result<Vec<String>, String> {
+	let table = text.parse::<toml::Table>().map_err(|e| e.to_string())?;
+	let mut errors = Vec::new();
+	for (key, value) in &table {
+		let result = spec(key).and_then(|spec| spec.from_toml(value)).and_then(|value| set(key, &value));
+		if let Err(e) = result {
+			errors.push(format!("{}: {}", key, e));
+		}
+	}
+	Ok(errors)
+}
+
+#[derive(Default)]
+pub(crate) struct SettingsWindow {
+	pub(crate) open: bool,
+	/// Text being typed into an editor, applied when it loses focus.
+	edits: HashMap<&'static str, String>,
+	errors: HashMap<&'static str, String>,
+	message: Option<String>,
+}
+
+impl SettingsWindow {
+	pub(crate) fn show(&mut self, ctx: &Context, secrets_ui: &mut super::secrets::SecretsUi) {
+		let mut open = self.open;
+
+		egui::Window::new("settings").open(&mut open).show(ctx, |ui| self.ui(ui, secrets_ui));
+
+		self.open = open;
+	}
+
+	pub(crate) fn ui(&mut self, ui: &mut Ui, secrets_ui: &mut super::secrets::SecretsUi) {
+		secrets_ui.ui(ui);
+
+		ui.separator();
+
+		super::server::token_ui(ui);
+
+		ui.separator();
+
+		Grid::new("settings").num_columns(3).striped(true).show(ui, |ui| {
+			for spec in REGISTRY {
+				ui.label(spec.key);
+				self.editor(ui, spec);
+				ui.label(RichText::new(spec.description).weak());
+				ui.end_row();
+			}
+		});
+
+		ui.separator();
+
+		ui.horizontal(|ui| {
+			if ui.button("export…").clicked()
+				&& let Some(path) = rfd::FileDialog::new().set_file_name("brace.toml").save_file()
+			{
+				self.message = std::fs::write(path, export_toml()).err().map(|e| e.to_string());
+			}
+			if ui.button("import…").clicked()
+				&& let Some(path) = rfd::FileDialog::new().add_filter("toml", &["toml"]).pick_file()
+			{
+				self.edits.clear();
+				self.message = match std::fs::read_to_string(path).map_err(|e| e.to_string()) {
+					Ok(text) => match import_toml(&text) {
+						Ok(errors) if errors.is_empty() => None,
+						Ok(errors) => Some(errors.join("\n")),
+						Err(e) => Some(e),
+					},
+					Err(e) => Some(e),
+				};
+			}
+		});
+
+		if let Some(message) = &self.message {
+			ui.colored_label(ui.visuals().error_fg_color, message);
+		}
+	}
+
+	fn editor(&mut self, ui: &mut Ui, spec: &'static Spec) {
+		let current = get(spec.key);
+
+		match spec.kind {
+			Kind::Choice(choices) => {
+				ComboBox::from_id_source(spec.key).selected_text(&current).show_ui(ui, |ui| {
+					for choice in choices {
+						if ui.selectable_label(current == *choice, *choice).clicked() {
+							self.apply(spec.key, choice);
+						}
+					}
+				});
+			}
+			Kind::Bool => {
+				let mut checked = current == "true";
+				if ui.checkbox(&mut checked, "").changed() {
+					self.apply(spec.key, &checked.to_string());
+				}
+			}
+			Kind::Color => {
+				ui.horizontal(|ui| {
+					let mut color = super::theme::parse_color(&current).unwrap_or(Color32::GRAY);
+					if ui.color_edit_button_srgba(&mut color).changed() {
+						self.apply(spec.key, &super::theme::format_color(color));
+					}
+					if current.is_empty() {
+						ui.label("default");
+					} else if ui.small_button("reset").clicked() {
+						self.apply(spec.key, "");
+					}
+				});
+			}
+			Kind::Text | Kind::Lines | Kind::Integer { .. } | Kind::File => {
+				ui.vertical(|ui| {
+					let edit = self.edits.entry(spec.key).or_insert(current);
+					let editor = match spec.kind {
+						Kind::Lines => TextEdit::multiline(edit).desired_rows(2),
+						_ => TextEdit::singleline(edit),
+					};
+					if ui.add(editor).lost_focus() {
+						let value = edit.clone();
+						self.apply(spec.key, &value);
+					}
+					if matches!(spec.kind, Kind::File)
+						&& ui.small_button("browse…").clicked()
+						&& let Some(path) = rfd::FileDialog::new().pick_file()
+					{
+						self.apply(spec.key, &path.display().to_string());
+					}
+					if let Some(error) = self.errors.get(spec.key) {
+						ui.colored_label(ui.visuals().error_fg_color, error);
+					}
+				});
+			}
+		}
+	}
+
+	fn apply(&mut self, key: &'static str, value: &str) {
+		match set(key, value) {
+			Ok(()) => {
+				self.errors.remove(key);
+				self.edits.remove(key);
+			}
+			Err(e) => {
+				self.errors.insert(key, e);
+			}
+		}
+	}
+}
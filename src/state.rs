@@ -0,0 +1,486 @@
+use super::*;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::{mpsc, oneshot};
+
+static NEXT_WINDOW: AtomicU64 = AtomicU64::new(0);
+static NEXT_MESSAGE: AtomicU64 = AtomicU64::new(0);
+
+/// How many closed windows can be reopened.
+const MAX_CLOSED: usize = 20;
+
+/// How many message changes each window can undo.
+const MAX_UNDO: usize = 50;
+
+const WORKSPACE_KEY: &str = "workspace";
+const LINKS_KEY: &str = "relay_links";
+
+/// Identifies a wheel window, whatever happens to the windows around it. Saved with the workspace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub(crate) struct WindowId(pub(crate) u64);
+
+/// Identifies a message, whatever happens to the messages around it. Saved with the workspace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub(crate) struct MessageId(pub(crate) u64);
+
+impl WindowId {
+	pub(crate) fn new() -> Self {
+		Self(NEXT_WINDOW.fetch_add(1, Ordering::Relaxed))
+	}
+}
+
+impl MessageId {
+	pub(crate) fn new() -> Self {
+		Self(NEXT_MESSAGE.fetch_add(1, Ordering::Relaxed))
+	}
+}
+
+impl WindowId {
+	/// The egui ID of the window, which also names its layer.
+	pub(crate) fn egui_id(self) -> Id {
+		Id::new(("wheel", self.0))
+	}
+}
+
+impl MessageId {
+	/// The egui ID of the message's editor.
+	pub(crate) fn editor_id(self) -> Id {
+		Id::new(("message", self.0))
+	}
+}
+
+impl std::fmt::Display for WindowId {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+/// A change requested by an async task, applied by the UI thread on its next frame.
+pub(crate) enum Event {
+	/// More streamed content for an assistant message.
+	Delta {
+		window: WindowId,
+		message: MessageId,
+		content: String,
+	},
+	/// A completion stream ended, with its error if it failed.
+	Finished {
+		window: WindowId,
+		message: MessageId,
+		error: Option<String>,
+	},
+	ListWindows {
+		reply: oneshot::Sender<Vec<WindowSummary>>,
+	},
+	/// Replies with the index the message was inserted at, or `None` if there's no such window.
+	AppendMessage {
+		window: WindowId,
+		message: ChatMessage,
+		reply: oneshot::Sender<Option<usize>>,
+	},
+	/// Progress of a comparison window's response.
+	Compare(compare::Update),
+	/// Progress of checking for or installing an update.
+	UpdateStatus(self_update::Status),
+}
+
+pub(crate) type Sender = mpsc::UnboundedSender<Event>;
+
+/// A change to one message, made with the buttons under it.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MessageAction {
+	Delete,
+	MoveUp,
+	MoveDown,
+	Duplicate,
+	TogglePin,
+}
+
+#[derive(Serialize)]
+pub(crate) struct WindowSummary {
+	pub(crate) id: u64,
+	pub(crate) open: bool,
+	pub(crate) model: String,
+	pub(crate) messages: usize,
+}
+
+/// State owned by the UI thread. Async tasks change it only by sending `Event`s.
+pub(crate) struct Store {
+	pub(crate) windows: Vec<WheelWindow>,
+	/// Inline completion shown after the cursor in the focused editor; Tab accepts its first line.
+	pub(crate) completion: String,
+	pub(crate) keymap: keymap::Keymap,
+	/// Closed windows, most recently closed last.
+	pub(crate) closed: Vec<WheelWindow>,
+	/// The window whose editor last had focus.
+	pub(crate) active: Option<WindowId>,
+	/// An editor to focus, if focus isn't elsewhere by the next frame.
+	pub(crate) restore_focus: Option<MessageId>,
+	/// An editor to focus on the next frame, regardless of where focus is.
+	pub(crate) focus: Option<MessageId>,
+	/// A message to jump to on the next frame, bringing its window to the front.
+	pub(crate) navigate: Option<(WindowId, MessageId)>,
+	/// Links relaying replies between windows.
+	pub(crate) links: Vec<relay::Link>,
+	pub(crate) compare_windows: Vec<compare::CompareWindow>,
+	/// Hashes of images known to be in the database, so saving the workspace doesn't look them up.
+	stored_images: HashSet<String>,
+	pub(crate) update_status: self_update::Status,
+	tx: Sender,
+	rx: mpsc::UnboundedReceiver<Event>,
+}
+
+impl Default for Store {
+	fn default() -> Self {
+		let (tx, rx) = mpsc::unbounded_channel();
+		Self {
+			windows: Vec::new(),
+			completion: String::new(),
+			keymap: keymap::Keymap::default(),
+			closed: Vec::new(),
+			active: None,
+			restore_focus: None,
+			focus: None,
+			navigate: None,
+			links: Vec::new(),
+			compare_windows: Vec::new(),
+			stored_images: HashSet::new(),
+			update_status: self_update::Status::Idle,
+			tx,
+			rx,
+		}
+	}
+}
+
+impl Store {
+	pub(crate) fn sender(&self) -> Sender {
+		self.tx.clone()
+	}
+
+	/// Saves the open windows, which one has focus, and the links between them. Window positions and sizes, scroll offsets
+	/// and the side panel width are saved by egui along with the rest of its memory.
+	pub(crate) fn save(&mut self, storage: &mut dyn eframe::Storage) {
+		self.store_images();
+		eframe::set_value(storage, WORKSPACE_KEY, &(&self.windows, self.active));
+		eframe::set_value(storage, LINKS_KEY, &self.links);
+	}
+
+	/// Puts the bytes of images in open windows into the database, which the saved workspace refers
+	/// to them in by hash.
+	fn store_images(&mut self) {
+		for image in self.windows.iter().flat_map(|w| &w.messages).flat_map(|m| &m.images) {
+			if self.stored_images.contains(&image.hash) {
+				continue;
+			}
+			match image.store() {
+				Ok(()) => {
+					self.stored_images.insert(image.hash.clone());
+				}
+				Err(e) => eprintln!("saving image: {}", e),
+			}
+		}
+	}
+
+	/// Restores the windows saved by `save` and refocuses the active one; returns false if there
+	/// was no saved workspace.
+	pub(crate) fn restore(&mut self, storage: &dyn eframe::Storage) -> bool {
+		let Some((windows, active)) =
+			eframe::get_value::<(Vec<WheelWindow>, Option<WindowId>)>(storage, WORKSPACE_KEY)
+		else {
+			return false;
+		};
+
+		// egui keys window and editor state by these IDs, so new ones mustn't reuse them.
+		for window in &windows {
+			NEXT_WINDOW.fetch_max(window.id.0 + 1, Ordering::Relaxed);
+			for message in &window.messages {
+				NEXT_MESSAGE.fetch_max(message.id.0 + 1, Ordering::Relaxed);
+			}
+		}
+
+		self.focus = windows.iter().find(|w| Some(w.id) == active).and_then(WheelWindow::focused_message);
+		self.windows = windows;
+		self.active = active;
+		self.links = eframe::get_value(storage, LINKS_KEY).unwrap_or_default();
+		true
+	}
+
+	pub(crate) fn window_mut(&mut self, id: WindowId) -> Option<&mut WheelWindow> {
+		self.windows.iter_mut().find(|w| w.id == id)
+	}
+
+	/// Adds a window and focuses its last message.
+	pub(crate) fn open(&mut self, window: WheelWindow) {
+		self.focus = window.messages.last().map(|m| m.id);
+		self.active = Some(window.id);
+		self.windows.push(window);
+	}
+
+	/// Moves windows that were closed this frame onto the undo stack, cancelling their streams.
+	pub(crate) fn remove_closed(&mut self) {
+		let (open, closed): (Vec<_>, Vec<_>) =
+			std::mem::take(&mut self.windows).into_iter().partition(|w| w.open);
+		self.windows = open;
+		for mut window in closed {
+			window.stream = None;
+			window.request_close = false;
+			if self.active == Some(window.id) {
+				self.active = None;
+				self.restore_focus = self.windows.last().and_then(|w| w.focused_message());
+			}
+			self.closed.push(window);
+		}
+		if self.closed.len() > MAX_CLOSED {
+			self.closed.drain(..self.closed.len() - MAX_CLOSED);
+		}
+	}
+
+	/// Reopens the most recently closed window.
+	pub(crate) fn reopen(&mut self) {
+		if let Some(mut window) = self.closed.pop() {
+			window.open = true;
+			self.focus = window.focused_message();
+			self.active = Some(window.id);
+			self.windows.push(window);
+		}
+	}
+
+	/// Jumps to `message` in `window` on the next frame, reopening the window if it was closed.
+	pub(crate) fn navigate_to(&mut self, window: WindowId, message: MessageId) {
+		if let Some(i) = self.closed.iter().position(|w| w.id == window) {
+			let mut closed = self.closed.remove(i);
+			closed.open = true;
+			self.windows.push(closed);
+		}
+		self.navigate = Some((window, message));
+	}
+
+	/// The editor to focus when moving `offset` windows away from the active one, wrapping around.
+	pub(crate) fn cycle_windows(&mut self, offset: isize) -> Option<(WindowId, MessageId)> {
+		let len = self.windows.len() as isize;
+		if len == 0 {
+			return None;
+		}
+		let current = self.active.and_then(|id| self.windows.iter().position(|w| w.id == id));
+		let next = current.map_or(0, |i| (i as isize + offset).rem_euclid(len) as usize);
+		let window = &self.windows[next];
+		Some((window.id, window.focused_message()?))
+	}
+
+	/// Applies every pending event. Events for windows or messages that have since been removed
+	/// are dropped. Replies that finish are relayed along any running links.
+	pub(crate) fn process_events(&mut self, ctx: &Context) {
+		while let Ok(event) = self.rx.try_recv() {
+			match event {
+				Event::Delta { window, message, content } => {
+					if let Some(message) = self.window_mut(window).and_then(|w| w.message_mut(message)) {
+						message.content.push_str(&content);
+						message.token_count = token_count(&message.content);
+					}
+				}
+				Event::Finished { window, message, error } => {
+					// Not `window_mut`, which would borrow all of `self`.
+					let Some(window) = self.windows.iter_mut().find(|w| w.id == window) else {
+						continue;
+					};
+					// A cancelled stream's reply is partial.
+					let finished = window.stream.as_ref().is_some_and(|(id, _)| *id == message);
+					if finished {
+						window.stream = None;
+						self.restore_focus = window.focused_message();
+					}
+					if error.is_some() {
+						window.error = error;
+					} else if finished {
+						let window = window.id;
+						relay::relay(self, ctx, window, message);
+					}
+				}
+				Event::ListWindows { reply } => {
+					reply.send(self.windows.iter().map(WheelWindow::summary).collect()).ok();
+				}
+				Event::AppendMessage { window, message, reply } => {
+					reply.send(self.window_mut(window).map(|w| w.append(message))).ok();
+				}
+				Event::Compare(update) => compare::apply(&mut self.compare_windows, update),
+				Event::UpdateStatus(status) => self.update_status = status,
+			}
+		}
+	}
+}
+
+impl WheelWindow {
+	/// The message whose editor last had focus, or else the last message.
+	pub(crate) fn focused_message(&self) -> Option<MessageId> {
+		self
+			.focused
+			.filter(|id| self.messages.iter().any(|m| m.id == *id))
+			.or(self.messages.last().map(|m| m.id))
+	}
+
+	pub(crate) fn message_mut(&mut self, id: MessageId) -> Option<&mut ChatMessage> {
+		self.messages.iter_mut().find(|m| m.id == id)
+	}
+
+	/// Inserts `message` before the empty prompt at the end, if there is one; returns its index.
+	pub(crate) fn append(&mut self, message: ChatMessage) -> usize {
+		let index = match self.messages.last() {
+			Some(last) if last.role == User && last.content.is_empty() => self.messages.len() - 1,
+			_ => self.messages.len(),
+		};
+		self.messages.insert(index, message);
+		index
+	}
+
+	/// A new window with copies of the messages up to `index`, then an empty prompt, and the same
+	/// parameters and context.
+	pub(crate) fn fork(&self, index: usize) -> WheelWindow {
+		let mut messages = self.messages[..=index]
+			.iter()
+			.map(|m| ChatMessage { id: MessageId::new(), ..m.clone() })
+			.collect::<Vec<_>>();
+		if !messages.last().is_some_and(|m| m.role == User) {
+			messages.push(ChatMessage::new(User, String::new()));
+		}
+		WheelWindow {
+			messages,
+			params: self.params.clone(),
+			context: self.context.clone(),
+			parent: Some((self.id, self.messages[index].id)),
+			..Default::default()
+		}
+	}
+
+	/// Saves the messages so the next change to them can be undone.
+	pub(crate) fn checkpoint(&mut self) {
+		self.undo.push(self.messages.clone());
+		if self.undo.len() > MAX_UNDO {
+			self.undo.remove(0);
+		}
+	}
+
+	/// Restores the messages from before the last change, cancelling the stream if its message is
+	/// gone; returns the message to focus.
+	pub(crate) fn undo(&mut self) -> Option<MessageId> {
+		self.messages = self.undo.pop()?;
+		if self.stream.as_ref().is_some_and(|(id, _)| !self.messages.iter().any(|m| m.id == *id)) {
+			self.stream = None;
+		}
+		self.focused_message()
+	}
+
+	/// Applies `action` to the message at `index`; returns the message to focus.
+	pub(crate) fn apply(&mut self, action: MessageAction, index: usize) -> Option<MessageId> {
+		let id = self.messages.get(index)?.id;
+		match action {
+			MessageAction::Delete => {
+				self.messages.remove(index);
+				if self.stream.as_ref().is_some_and(|(stream, _)| *stream == id) {
+					self.stream = None;
+				}
+				if self.messages.is_empty() {
+					self.messages.push(ChatMessage::new(User, String::new()));
+				}
+				return Some(self.messages[index.min(self.messages.len() - 1)].id);
+			}
+			MessageAction::MoveUp if index > 0 => self.messages.swap(index, index - 1),
+			MessageAction::MoveDown if index + 1 < self.messages.len() => {
+				self.messages.swap(index, index + 1)
+			}
+			MessageAction::MoveUp | MessageAction::MoveDown => {}
+			MessageAction::Duplicate => {
+				let copy = ChatMessage { id: MessageId::new(), ..self.messages[index].clone() };
+				let copy_id = copy.id;
+				self.messages.insert(index + 1, copy);
+				return Some(copy_id);
+			}
+			MessageAction::TogglePin => self.messages[index].pinned ^= true,
+		}
+		Some(id)
+	}
+
+	/// Removes the messages after `index`, except pinned ones, which move up to just before it so
+	/// they're still sent.
+	pub(crate) fn truncate_after(&mut self, index: usize) {
+		let pinned = self.messages.split_off(index + 1).into_iter().filter(|m| m.pinned);
+		self.messages.splice(index..index, pinned);
+	}
+
+	fn summary(&self) -> WindowSummary {
+		WindowSummary {
+			id: self.id.0,
+			open: self.open,
+			model: self.params.model.clone(),
+			messages: self.messages.len(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn window<const N: usize>(messages: [(Role, &str); N]) -> WheelWindow {
+		let messages = messages.map(|(role, content)| ChatMessage::new(role, content.into())).to_vec();
+		WheelWindow { messages, ..Default::default() }
+	}
+
+	fn contents(window: &WheelWindow) -> Vec<&str> {
+		window.messages.iter().map(|m| m.content.as_str()).collect()
+	}
+
+	#[test]
+	fn deleting_the_last_message_leaves_an_empty_prompt() {
+		let mut window = window([(User, "hi")]);
+		let focus = window.apply(MessageAction::Delete, 0);
+		assert_eq!(contents(&window), [""]);
+		assert_eq!(window.messages[0].role, User);
+		assert_eq!(focus, Some(window.messages[0].id));
+	}
+
+	#[test]
+	fn deleting_the_bottom_message_focuses_the_one_above() {
+		let mut window = window([(User, "hi"), (Assistant, "hello")]);
+		let focus = window.apply(MessageAction::Delete, 1);
+		assert_eq!(contents(&window), ["hi"]);
+		assert_eq!(focus, Some(window.messages[0].id));
+	}
+
+	#[test]
+	fn undoing_a_delete_of_the_streaming_message_leaves_the_stream_cancelled() {
+		let mut window = window([(User, "hi"), (Assistant, "hel"), (User, "")]);
+		let (trigger, _tripwire) = Tripwire::new();
+		window.stream = Some((window.messages[1].id, trigger));
+
+		window.checkpoint();
+		window.apply(MessageAction::Delete, 1);
+		assert!(window.stream.is_none());
+
+		window.undo();
+		assert_eq!(contents(&window), ["hi", "hel", ""]);
+		assert!(window.stream.is_none());
+		assert!(window.undo.is_empty());
+	}
+
+	#[test]
+	fn pinned_messages_move_to_just_before_the_prompt() {
+		let mut window =
+			window([(User, "a"), (Assistant, "b"), (User, "pinned"), (Assistant, "c"), (User, "d")]);
+		window.messages[2].pinned = true;
+		window.truncate_after(1);
+		assert_eq!(contents(&window), ["a", "pinned", "b"]);
+		assert!(window.messages[1].pinned);
+	}
+
+	#[test]
+	fn fork_links_to_the_message_it_was_forked_from() {
+		let window = window([(User, "hi"), (Assistant, "hello"), (User, "more")]);
+		let fork = window.fork(1);
+		assert_eq!(fork.parent, Some((window.id, window.messages[1].id)));
+		assert_eq!(contents(&fork), ["hi", "hello", ""]);
+		assert_ne!(fork.id, window.id);
+		assert!(fork.messages.iter().zip(&window.messages).all(|(f, m)| f.id != m.id));
+	}
+}
@@ -0,0 +1,193 @@
+use super::*;
+use tokio::sync::broadcast::{self, error::TryRecvError};
+
+/// Settings that change how the app looks.
+const KEYS: &[&str] = &[
+	"theme",
+	"ui_zoom",
+	"editor_font",
+	"editor_font_size",
+	"document_font",
+	"document_font_size",
+	"custom_font",
+	"user_color",
+	"assistant_color",
+	"system_color",
+];
+
+/// Family of the font loaded from the `custom_font` setting.
+const CUSTOM_FAMILY: &str = "custom";
+
+/// Text style of message editors.
+pub(crate) fn editor_font(ui: &Ui) -> FontId {
+	font(ui, "editor", FontId::monospace(20.0))
+}
+
+/// Text style of large free-form editors.
+pub(crate) fn document_font(ui: &Ui) -> FontId {
+	font(ui, "document", FontId::proportional(30.0))
+}
+
+fn font(ui: &Ui, style: &str, default: FontId) -> FontId {
+	ui.style().text_styles.get(&TextStyle::Name(style.into())).cloned().unwrap_or(default)
+}
+
+#[derive(Clone, Copy)]
+struct RoleColors {
+	user: Color32,
+	assistant: Color32,
+	system: Color32,
+}
+
+impl RoleColors {
+	const DARK: Self = Self {
+		user: Color32::from_rgb(230, 230, 230),
+		assistant: Color32::from_rgb(0xCD, 0xD8, 0xFF),
+		system: Color32::from_rgb(230, 230, 230),
+	};
+
+	const LIGHT: Self = Self {
+		user: Color32::from_rgb(30, 30, 30),
+		assistant: Color32::from_rgb(0x1F, 0x3A, 0x93),
+		system: Color32::from_rgb(30, 30, 30),
+	};
+}
+
+fn role_colors_id() -> Id {
+	Id::new("role_colors")
+}
+
+/// Color of a message's text in editors.
+pub(crate) fn role_color(ui: &Ui, role: Role) -> Color32 {
+	let colors = ui.data(|d| d.get_temp::<RoleColors>(role_colors_id())).unwrap_or(RoleColors::DARK);
+	match role {
+		Assistant => colors.assistant,
+		System => colors.system,
+		_ => colors.user,
+	}
+}
+
+/// Parses `#rrggbb`.
+pub(crate) fn parse_color(value: &str) -> Option<Color32> {
+	let hex = value.strip_prefix('#').filter(|hex| hex.len() == 6)?;
+	let rgb = u32::from_str_radix(hex, 16).ok()?;
+	Some(Color32::from_rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
+
+pub(crate) fn format_color(color: Color32) -> String {
+	format!("#{:02x}{:02x}{:02x}", color.r(), color.g(), color.b())
+}
+
+/// Applies the theme and typography settings whenever they, or the system theme, change.
+pub(crate) struct Theme {
+	changes: broadcast::Receiver<&'static str>,
+	/// The system theme last applied for, or `None` before the first frame.
+	system_theme: Option<Option<eframe::Theme>>,
+	/// The `custom_font` path currently loaded, and whether it loaded.
+	custom_font: Option<(String, bool)>,
+	/// Set when fonts were replaced, which only takes effect on the next frame, so styles that use
+	/// them wait until then.
+	restyle: bool,
+}
+
+impl Default for Theme {
+	fn default() -> Self {
+		Self { changes: settings::subscribe(), system_theme: None, custom_font: None, restyle: false }
+	}
+}
+
+impl Theme {
+	pub(crate) fn apply(&mut self, ctx: &Context, frame: &eframe::Frame) {
+		let mut changed = std::mem::take(&mut self.restyle);
+		loop {
+			match self.changes.try_recv() {
+				Ok(key) => changed |= KEYS.contains(&key),
+				Err(TryRecvError::Lagged(_)) => changed = true,
+				Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+			}
+		}
+		let system_theme = frame.info().system_theme;
+		if self.system_theme != Some(system_theme) {
+			// eframe also switches visuals when the system theme changes.
+			self.system_theme = Some(system_theme);
+			changed = true;
+		}
+		if !changed {
+			return;
+		}
+
+		let dark = match settings::get("theme").as_str() {
+			"dark" => true,
+			"light" => false,
+			_ => system_theme != Some(eframe::Theme::Light),
+		};
+		ctx.set_visuals(if dark { Visuals::dark() } else { Visuals::light() });
+
+		let defaults = if dark { RoleColors::DARK } else { RoleColors::LIGHT };
+		let color = |key: &str, default: Color32| parse_color(&settings::get(key)).unwrap_or(default);
+		let colors = RoleColors {
+			user: color("user_color", defaults.user),
+			assistant: color("assistant_color", defaults.assistant),
+			system: color("system_color", defaults.system),
+		};
+		ctx.data_mut(|d| d.insert_temp(role_colors_id(), colors));
+
+		ctx.set_zoom_factor(settings::get("ui_zoom").parse::<f32>().unwrap() / 100.0);
+
+		let path = settings::get("custom_font");
+		if self.custom_font.as_ref().map(|(p, _)| p) != Some(&path) {
+			let loaded = load_fonts(ctx, &path);
+			self.custom_font = Some((path, loaded));
+			self.restyle = true;
+			ctx.request_repaint();
+			return;
+		}
+		let custom_loaded = self.custom_font.as_ref().is_some_and(|(_, loaded)| *loaded);
+
+		let family = |key: &str, fallback: FontFamily| match settings::get(key).as_str() {
+			"monospace" => FontFamily::Monospace,
+			"proportional" => FontFamily::Proportional,
+			"custom" if custom_loaded => FontFamily::Name(CUSTOM_FAMILY.into()),
+			_ => fallback,
+		};
+		let size = |key: &str| settings::get(key).parse::<f32>().unwrap();
+		let editor = FontId::new(size("editor_font_size"), family("editor_font", FontFamily::Monospace));
+		let document =
+			FontId::new(size("document_font_size"), family("document_font", FontFamily::Proportional));
+		ctx.style_mut(|style| {
+			style.text_styles.insert(TextStyle::Name("editor".into()), editor);
+			style.text_styles.insert(TextStyle::Name("document".into()), document);
+		});
+	}
+}
+
+/// Replaces the fonts with the defaults plus the TTF or OTF file at `path`, if any, falling back to
+/// the default fonts for glyphs it lacks. Returns whether it loaded.
+fn load_fonts(ctx: &Context, path: &str) -> bool {
+	let mut fonts = FontDefinitions::default();
+	let loaded = !path.is_empty()
+		&& match std::fs::read(path) {
+			// egui panics on data it can't parse as a font.
+			Ok(bytes) if !is_font(&bytes) => {
+				eprintln!("{}: not a TrueType or OpenType font", path);
+				false
+			}
+			Ok(bytes) => {
+				fonts.font_data.insert(CUSTOM_FAMILY.into(), FontData::from_owned(bytes));
+				let mut family = vec![CUSTOM_FAMILY.to_owned()];
+				family.extend(fonts.families[&FontFamily::Proportional].iter().cloned());
+				fonts.families.insert(FontFamily::Name(CUSTOM_FAMILY.into()), family);
+				true
+			}
+			Err(e) => {
+				eprintln!("{}: {}", path, e);
+				false
+			}
+		};
+	ctx.set_fonts(fonts);
+	loaded
+}
+
+fn is_font(bytes: &[u8]) -> bool {
+	matches!(bytes.get(..4), Some(b"\x00\x01\x00\x00" | b"true" | b"OTTO" | b"ttcf"))
+}
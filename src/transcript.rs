@@ -0,0 +1,462 @@
+use super::*;
+use std::path::Path;
+
+/// Formats a wheel window's messages can be exported to and imported from.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Format {
+	/// A `## role` heading before each message, with images as `![image](data:...)` lines. Lines of
+	/// content that would read as headings get a `\` in front.
+	Markdown,
+	/// An array of OpenAI chat messages, or a `{"messages": [...]}` object.
+	Json,
+	/// One `{"messages": [...]}` line per conversation, as used for fine-tuning.
+	Jsonl,
+	/// `conversations.json` from a ChatGPT data export. Import only.
+	ChatGpt,
+}
+
+impl Format {
+	pub(crate) const EXPORT: [Format; 3] = [Format::Markdown, Format::Json, Format::Jsonl];
+
+	pub(crate) fn label(self) -> &'static str {
+		match self {
+			Format::Markdown => "Markdown",
+			Format::Json => "JSON",
+			Format::Jsonl => "JSONL (fine-tuning)",
+			Format::ChatGpt => "ChatGPT export",
+		}
+	}
+
+	pub(crate) fn extension(self) -> &'static str {
+		match self {
+			Format::Markdown => "md",
+			Format::Json | Format::ChatGpt => "json",
+			Format::Jsonl => "jsonl",
+		}
+	}
+
+	/// Guesses the format of `text` from the file it was read from.
+	pub(crate) fn detect(path: &Path, text: &str) -> Self {
+		let extension = path.extension().and_then(|e| e.to_str());
+		match (extension, serde_json::from_str::<serde_json::Value>(text)) {
+			(Some("md" | "markdown" | "txt"), _) => Format::Markdown,
+			(Some("jsonl"), _) => Format::Jsonl,
+			(_, Ok(serde_json::Value::Array(items)))
+				if items.first().is_some_and(|item| item.get("mapping").is_some()) =>
+			{
+				Format::ChatGpt
+			}
+			(Some("json"), _) | (_, Ok(_)) => Format::Json,
+			// Several JSON values, one per line.
+			(_, Err(_)) if text.trim_start().starts_with('{') => Format::Jsonl,
+			(_, Err(_)) => Format::Markdown,
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+struct Message {
+	role: Role,
+	/// `null` for assistant messages that only call tools.
+	content: Option<Content>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Content {
+	Text(String),
+	Parts(Vec<Part>),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Part {
+	Text { text: String },
+	ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Serialize, Deserialize)]
+struct ImageUrl {
+	url: String,
+}
+
+/// A line of a fine-tuning JSONL file.
+#[derive(Serialize, Deserialize)]
+struct Example {
+	messages: Vec<Message>,
+}
+
+impl From<&ChatMessage> for Message {
+	fn from(message: &ChatMessage) -> Self {
+		let content = if message.images.is_empty() {
+			Content::Text(message.content.clone())
+		} else {
+			let mut parts = vec![Part::Text { text: message.content.clone() }];
+			parts.extend(
+				message
+					.images
+					.iter()
+					.map(|image| Part::ImageUrl { image_url: ImageUrl { url: image.data_url() } }),
+			);
+			Content::Parts(parts)
+		};
+		Self { role: message.role, content: Some(content) }
+	}
+}
+
+impl Message {
+	fn into_chat_message(self) -> Result<ChatMessage, Box<dyn std::error::Error>> {
+		check_role(self.role)?;
+		let (text, images) = match self.content {
+			None => (String::new(), Vec::new()),
+			Some(Content::Text(text)) => (text, Vec::new()),
+			Some(Content::Parts(parts)) => {
+				let mut texts = Vec::new();
+				let mut images = Vec::new();
+				for part in parts {
+					match part {
+						Part::Text { text } => texts.push(text),
+						Part::ImageUrl { image_url } => images.push(ImageAttachment::from_data_url(&image_url.url)?),
+					}
+				}
+				(texts.join("\n\n"), images)
+			}
+		};
+		Ok(chat_message(self.role, text, images))
+	}
+}
+
+#[derive(Deserialize)]
+struct ChatGptConversation {
+	mapping: HashMap<String, ChatGptNode>,
+	current_node: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatGptNode {
+	message: Option<ChatGptMessage>,
+	parent: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatGptMessage {
+	author: ChatGptAuthor,
+	content: ChatGptContent,
+}
+
+#[derive(Deserialize)]
+struct ChatGptAuthor {
+	role: String,
+}
+
+#[derive(Deserialize)]
+struct ChatGptContent {
+	/// Strings for text; objects for images and other attachments, which are skipped.
+	#[serde(default)]
+	parts: Vec<serde_json::Value>,
+}
+
+fn chat_message(role: Role, content: String, images: Vec<ImageAttachment>) -> ChatMessage {
+	ChatMessage { images, ..ChatMessage::new(role, content) }
+}
+
+/// `run_openai` only handles these roles.
+pub(crate) fn check_role(role: Role) -> Result<(), String> {
+	match role {
+		System | User | Assistant => Ok(()),
+		role => Err(format!("unsupported role {}", role)),
+	}
+}
+
+fn parse_role(name: &str) -> Option<Role> {
+	serde_json::from_value(serde_json::Value::String(name.to_owned()))
+		.ok()
+		.filter(|&role| check_role(role).is_ok())
+}
+
+/// Whether `line`, with any `\` escapes in front removed, is a `## role` heading.
+fn is_heading(line: &str) -> bool {
+	line.trim_start_matches('\\').trim_end().strip_prefix("## ").and_then(parse_role).is_some()
+}
+
+/// Serializes `messages`, leaving out trailing empty ones such as the unsent prompt.
+pub(crate) fn export(format: Format, messages: &[ChatMessage]) -> String {
+	let len =
+		messages.iter().rposition(|m| !m.content.is_empty() || !m.images.is_empty()).map_or(0, |i| i + 1);
+	let messages = &messages[..len];
+
+	match format {
+		Format::Markdown => messages.iter().map(to_markdown).collect(),
+		Format::Json => {
+			serde_json::to_string_pretty(&messages.iter().map(Message::from).collect::<Vec<_>>()).unwrap()
+		}
+		Format::Jsonl => {
+			let example = Example { messages: messages.iter().map(Message::from).collect() };
+			serde_json::to_string(&example).unwrap() + "\n"
+		}
+		Format::ChatGpt => unreachable!("ChatGPT exports can only be imported"),
+	}
+}
+
+fn to_markdown(message: &ChatMessage) -> String {
+	let content = message
+		.content
+		.split_inclusive('\n')
+		.map(|line| if is_heading(line) { format!("\\{}", line) } else { line.to_owned() })
+		.collect::<String>();
+	let mut text = format!("## {}\n\n{}\n\n", message.role, content);
+	for image in &message.images {
+		text.push_str(&format!("![image]({})\n\n", image.data_url()));
+	}
+	text
+}
+
+/// Parses one or more conversations.
+pub(crate) fn import(
+	format: Format,
+	text: &str,
+) -> Result<Vec<Vec<ChatMessage>>, Box<dyn std::error::Error>> {
+	match format {
+		Format::Markdown => Ok(vec![from_markdown(text)?]),
+		Format::Json => match serde_json::from_str(text)? {
+			serde_json::Value::Object(mut example) => {
+				Ok(vec![parse_messages(example.remove("messages").ok_or("expected a messages array")?)?])
+			}
+			messages => Ok(vec![parse_messages(messages)?]),
+		},
+		Format::Jsonl => text
+			.lines()
+			.filter(|line| !line.trim().is_empty())
+			.map(|line| -> Result<Vec<_>, Box<dyn std::error::Error>> {
+				serde_json::from_str::<Example>(line)?
+					.messages
+					.into_iter()
+					.map(Message::into_chat_message)
+					.collect()
+			})
+			.collect(),
+		Format::ChatGpt => Ok(
+			serde_json::from_str::<Vec<ChatGptConversation>>(text)?
+				.into_iter()
+				.map(from_chatgpt)
+				.filter(|messages| !messages.is_empty())
+				.collect(),
+		),
+	}
+}
+
+/// Parses an array of OpenAI chat messages.
+pub(crate) fn parse_messages(
+	messages: serde_json::Value,
+) -> Result<Vec<ChatMessage>, Box<dyn std::error::Error>> {
+	serde_json::from_value::<Vec<Message>>(messages)?
+		.into_iter()
+		.map(Message::into_chat_message)
+		.collect()
+}
+
+pub(crate) fn import_file(
+	path: &Path,
+) -> Result<Vec<Vec<ChatMessage>>, Box<dyn std::error::Error>> {
+	let text = std::fs::read_to_string(path)?;
+	import(Format::detect(path, &text), &text)
+}
+
+fn from_markdown(text: &str) -> Result<Vec<ChatMessage>, Box<dyn std::error::Error>> {
+	// (role, heading start, body start)
+	let mut sections = Vec::new();
+	let mut offset = 0;
+	for line in text.split_inclusive('\n') {
+		if let Some(role) = line.trim_end().strip_prefix("## ").and_then(parse_role) {
+			sections.push((role, offset, offset + line.len()));
+		}
+		offset += line.len();
+	}
+	let unescape = |body: &str| {
+		body
+			.split_inclusive('\n')
+			.map(|line| if is_heading(line) { line.strip_prefix('\\').unwrap_or(line) } else { line })
+			.collect::<String>()
+	};
+	if sections.is_empty() {
+		Err("no `## role` headings found")?;
+	}
+
+	let mut messages = Vec::new();
+	for (i, &(role, _, start)) in sections.iter().enumerate() {
+		let end = sections.get(i + 1).map_or(text.len(), |&(_, heading, _)| heading);
+		let body = &text[start..end];
+		let body = body.strip_prefix('\n').unwrap_or(body);
+		let mut body = body.strip_suffix("\n\n").unwrap_or_else(|| body.trim_end_matches('\n'));
+
+		let mut images = Vec::new();
+		while let Some((rest, last)) = body.rsplit_once("\n\n")
+			&& let Some(url) = last.strip_prefix("![image](").and_then(|l| l.strip_suffix(')'))
+			&& url.starts_with("data:")
+		{
+			images.push(ImageAttachment::from_data_url(url)?);
+			body = rest;
+		}
+		images.reverse();
+
+		messages.push(chat_message(role, unescape(body), images));
+	}
+	Ok(messages)
+}
+
+/// Follows the branch ending at `current_node`, which is the one shown in ChatGPT.
+fn from_chatgpt(conversation: ChatGptConversation) -> Vec<ChatMessage> {
+	let mut messages = Vec::new();
+	let mut node_id = conversation.current_node;
+
+	while let Some(node) = node_id.and_then(|id| conversation.mapping.get(&id)) {
+		if let Some(message) = &node.message
+			&& let Some(role) = parse_role(&message.author.role)
+		{
+			let parts = message.content.parts.iter().filter_map(|p| p.as_str()).collect::<Vec<_>>();
+			let text = parts.join("\n");
+			if !text.trim().is_empty() {
+				messages.push(chat_message(role, text, Vec::new()));
+			}
+		}
+		node_id = node.parent.clone();
+	}
+
+	messages.reverse();
+	messages
+}
+
+/// Asks where to save `messages`, then writes them in `format`.
+pub(crate) fn save(format: Format, messages: &[ChatMessage], name: &str) {
+	let Some(path) = rfd::FileDialog::new()
+		.add_filter(format.label(), &[format.extension()])
+		.set_file_name(format!("{}.{}", name, format.extension()))
+		.save_file()
+	else {
+		return;
+	};
+	if let Err(e) = std::fs::write(&path, export(format, messages)) {
+		eprintln!("{}: {}", path.display(), e);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn png() -> ImageAttachment {
+		let mut bytes = std::io::Cursor::new(Vec::new());
+		image::RgbImage::new(2, 1).write_to(&mut bytes, image::ImageFormat::Png).unwrap();
+		ImageAttachment::from_bytes("test.png", bytes.into_inner()).unwrap()
+	}
+
+	fn conversation() -> Vec<ChatMessage> {
+		vec![
+			chat_message(System, "Be brief.".into(), Vec::new()),
+			chat_message(User, "What's this?\n\n## user\n\\## assistant\n".into(), vec![png(), png()]),
+			chat_message(Assistant, "## system\nA pixel.\n\n```\n## user\n```".into(), Vec::new()),
+			chat_message(User, String::new(), vec![png()]),
+		]
+	}
+
+	fn summary(messages: &[ChatMessage]) -> Vec<(Role, String, Vec<String>)> {
+		messages
+			.iter()
+			.map(|m| (m.role, m.content.clone(), m.images.iter().map(|i| i.data_url()).collect()))
+			.collect()
+	}
+
+	fn round_trip(format: Format) {
+		let messages = conversation();
+		let imported = import(format, &export(format, &messages)).unwrap();
+		assert_eq!(imported.len(), 1);
+		assert_eq!(summary(&imported[0]), summary(&messages));
+	}
+
+	#[test]
+	fn markdown_round_trip() {
+		round_trip(Format::Markdown);
+	}
+
+	#[test]
+	fn json_round_trip() {
+		round_trip(Format::Json);
+	}
+
+	#[test]
+	fn jsonl_round_trip() {
+		round_trip(Format::Jsonl);
+	}
+
+	#[test]
+	fn export_drops_trailing_empty_prompt() {
+		let mut messages = conversation();
+		messages.push(chat_message(User, String::new(), Vec::new()));
+		let imported = import(Format::Json, &export(Format::Json, &messages)).unwrap();
+		assert_eq!(imported[0].len(), conversation().len());
+	}
+
+	#[test]
+	fn detects_pretty_printed_json_object() {
+		let text = serde_json::to_string_pretty(&serde_json::json!({
+			"messages": [{ "role": "user", "content": "hi" }]
+		}))
+		.unwrap();
+		assert!(Format::detect(Path::new("a.json"), &text) == Format::Json);
+		assert_eq!(import(Format::Json, &text).unwrap()[0][0].content, "hi");
+	}
+
+	#[test]
+	fn detects_formats() {
+		let jsonl = export(Format::Jsonl, &conversation()).repeat(2);
+		assert!(Format::detect(Path::new("a"), &jsonl) == Format::Jsonl);
+		assert!(Format::detect(Path::new("a.json"), "[]") == Format::Json);
+		assert!(Format::detect(Path::new("a.json"), r#"[{"mapping": {}}]"#) == Format::ChatGpt);
+		assert!(Format::detect(Path::new("a"), "## user\n\nhi") == Format::Markdown);
+	}
+
+	#[test]
+	fn chatgpt_imports_only_the_current_branch() {
+		use serde_json::json;
+		fn node(role: &str, parts: serde_json::Value, parent: &str) -> serde_json::Value {
+			json!({
+				"message": { "author": { "role": role }, "content": { "parts": parts } },
+				"parent": parent,
+			})
+		}
+		let image = json!({ "content_type": "image_asset_pointer" });
+		let text = json!([{
+			"current_node": "bye",
+			"mapping": {
+				"root": { "message": null, "parent": null },
+				"system": node("system", json!(["Be brief."]), "root"),
+				"hi": node("user", json!(["Hi"]), "system"),
+				"abandoned": node("assistant", json!(["Old answer."]), "hi"),
+				"hello": node("assistant", json!(["Hello", image, "there"]), "hi"),
+				"search": node("tool", json!(["Search results."]), "hello"),
+				"review": node("critic", json!(["Looks fine."]), "search"),
+				"empty": node("user", json!(["", " \n"]), "review"),
+				"bye": node("assistant", json!(["Bye."]), "empty"),
+			},
+		}])
+		.to_string();
+
+		let imported = import(Format::ChatGpt, &text).unwrap();
+		let messages = imported[0].iter().map(|m| (m.role, m.content.as_str())).collect::<Vec<_>>();
+		assert_eq!(
+			messages,
+			[(System, "Be brief."), (User, "Hi"), (Assistant, "Hello\nthere"), (Assistant, "Bye.")]
+		);
+	}
+
+	#[test]
+	fn rejects_roles_run_openai_cannot_send() {
+		for role in ["tool", "function"] {
+			let json = format!(r#"[{{"role": "{}", "content": "x"}}]"#, role);
+			assert!(import(Format::Json, &json).is_err());
+			let line = format!(r#"{{"messages": [{{"role": "{}", "content": "x"}}]}}"#, role);
+			assert!(import(Format::Jsonl, &line).is_err());
+		}
+		assert!(import(Format::Markdown, "## tool\n\nx\n").is_err());
+	}
+}